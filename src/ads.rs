@@ -0,0 +1,131 @@
+use crate::hashing::{hash_file, HashAlgorithm, HashOptions};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Hashes every NTFS alternate data stream of `path`, keyed by stream name.
+///
+/// Returns `Ok(None)` when the platform or volume has no notion of alternate
+/// streams, so callers can skip the check instead of failing.
+pub fn hash_streams(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    options: &HashOptions,
+) -> io::Result<Option<BTreeMap<String, String>>> {
+    let names = match list_streams(path)? {
+        Some(names) => names,
+        None => return Ok(None),
+    };
+
+    let mut streams = BTreeMap::new();
+    for name in names {
+        let mut stream_path = path.as_os_str().to_owned();
+        stream_path.push(":");
+        stream_path.push(&name);
+        let hash = hash_file(Path::new(&stream_path), algorithm, options)?;
+        streams.insert(name, hash);
+    }
+    Ok(Some(streams))
+}
+
+/// Describes how the recorded streams differ from the ones found on disk,
+/// or `None` if they are identical.
+pub fn describe_mismatch(
+    expected: &BTreeMap<String, String>,
+    actual: &BTreeMap<String, String>,
+) -> Option<String> {
+    let mut problems = Vec::new();
+    for (name, hash) in expected {
+        match actual.get(name) {
+            None => problems.push(format!("missing stream '{}'", name)),
+            Some(actual_hash) if actual_hash != hash => {
+                problems.push(format!("changed stream '{}'", name))
+            }
+            Some(_) => {}
+        }
+    }
+    for name in actual.keys().filter(|name| !expected.contains_key(*name)) {
+        problems.push(format!("unexpected stream '{}'", name));
+    }
+
+    if problems.is_empty() {
+        None
+    } else {
+        Some(format!(
+            "Alternate data stream mismatch: {}",
+            problems.join(", ")
+        ))
+    }
+}
+
+#[cfg(windows)]
+fn list_streams(path: &Path) -> io::Result<Option<Vec<String>>> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::shared::winerror::{
+        ERROR_HANDLE_EOF, ERROR_INVALID_FUNCTION, ERROR_INVALID_PARAMETER,
+    };
+    use winapi::um::fileapi::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+    };
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+
+    // Not exported by winapi 0.3; layout from the Windows SDK.
+    #[repr(C)]
+    struct Win32FindStreamData {
+        stream_size: i64,
+        stream_name: [u16; 260 + 36],
+    }
+
+    fn stream_name(data: &Win32FindStreamData) -> Option<String> {
+        let len = data
+            .stream_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(data.stream_name.len());
+        let raw = String::from_utf16_lossy(&data.stream_name[..len]);
+        // Entries look like ":name:$DATA"; the unnamed "::$DATA" is the file content.
+        let name = raw.strip_prefix(':')?.strip_suffix(":$DATA")?;
+        (!name.is_empty()).then(|| name.to_string())
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = Win32FindStreamData {
+        stream_size: 0,
+        stream_name: [0; 296],
+    };
+
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            &mut data as *mut _ as *mut _,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error().map(|code| code as u32) {
+            Some(ERROR_HANDLE_EOF) => Ok(Some(Vec::new())),
+            Some(ERROR_INVALID_FUNCTION) | Some(ERROR_INVALID_PARAMETER) => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let mut names = Vec::new();
+    loop {
+        names.extend(stream_name(&data));
+        if unsafe { FindNextStreamW(handle, &mut data as *mut _ as *mut _) } == 0 {
+            let err = io::Error::last_os_error();
+            unsafe { FindClose(handle) };
+            return match err.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_HANDLE_EOF) => Ok(Some(names)),
+                _ => Err(err),
+            };
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn list_streams(_path: &Path) -> io::Result<Option<Vec<String>>> {
+    Ok(None)
+}
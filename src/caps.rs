@@ -0,0 +1,158 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// Normalized form of a file's `security.capability` xattr.
+///
+/// A file without capabilities is represented by the default (empty) value,
+/// so that capabilities gained on the target are still detected.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub permitted: Vec<String>,
+    pub inheritable: Vec<String>,
+    pub effective: bool,
+}
+
+const CAP_NAMES: [&str; 41] = [
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+const VFS_CAP_REVISION_MASK: u32 = 0xFF00_0000;
+const VFS_CAP_REVISION_1: u32 = 0x0100_0000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+/// Reads the capabilities of `path`. Returns `Ok(None)` on platforms without
+/// file capabilities.
+#[cfg(target_os = "linux")]
+pub fn read_capabilities(path: &Path) -> io::Result<Option<Capabilities>> {
+    match xattr::get(path, "security.capability")? {
+        Some(raw) => parse(&raw).map(Some).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unrecognized security.capability format",
+            )
+        }),
+        None => Ok(Some(Capabilities::default())),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read_capabilities(_path: &Path) -> io::Result<Option<Capabilities>> {
+    Ok(None)
+}
+
+/// Decodes a little-endian `vfs_cap_data` structure (revisions 1 to 3).
+pub fn parse(raw: &[u8]) -> Option<Capabilities> {
+    let word = |index: usize| -> Option<u32> {
+        let bytes = raw.get(index * 4..index * 4 + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    };
+
+    let magic = word(0)?;
+    // Revision 1 stores one 32-bit set pair, later revisions store two.
+    let pairs = if magic & VFS_CAP_REVISION_MASK == VFS_CAP_REVISION_1 {
+        1
+    } else {
+        2
+    };
+
+    let mut permitted = Vec::new();
+    let mut inheritable = Vec::new();
+    for pair in 0..pairs {
+        let permitted_bits = word(1 + pair * 2)?;
+        let inheritable_bits = word(2 + pair * 2)?;
+        for bit in 0..32 {
+            let cap = pair * 32 + bit;
+            if permitted_bits & (1 << bit) != 0 {
+                permitted.push(cap_name(cap));
+            }
+            if inheritable_bits & (1 << bit) != 0 {
+                inheritable.push(cap_name(cap));
+            }
+        }
+    }
+
+    Some(Capabilities {
+        permitted,
+        inheritable,
+        effective: magic & VFS_CAP_FLAGS_EFFECTIVE != 0,
+    })
+}
+
+fn cap_name(cap: usize) -> String {
+    CAP_NAMES
+        .get(cap)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| format!("cap_{}", cap))
+}
+
+/// Describes how the capabilities on disk differ from the recorded ones,
+/// or `None` if they are identical.
+pub fn describe_mismatch(expected: &Capabilities, actual: &Capabilities) -> Option<String> {
+    let mut problems = Vec::new();
+    for (set, expected, actual) in [
+        ("permitted", &expected.permitted, &actual.permitted),
+        ("inheritable", &expected.inheritable, &actual.inheritable),
+    ] {
+        for cap in actual.iter().filter(|cap| !expected.contains(cap)) {
+            problems.push(format!("added {} ({})", cap, set));
+        }
+        for cap in expected.iter().filter(|cap| !actual.contains(cap)) {
+            problems.push(format!("removed {} ({})", cap, set));
+        }
+    }
+    if expected.effective != actual.effective {
+        problems.push(format!(
+            "effective flag changed from {} to {}",
+            expected.effective, actual.effective
+        ));
+    }
+
+    if problems.is_empty() {
+        None
+    } else {
+        Some(format!("Capability mismatch: {}", problems.join(", ")))
+    }
+}
@@ -0,0 +1,127 @@
+//! Checkpoint files, which let an interrupted generate run pick up where it
+//! stopped instead of hashing every file again.
+
+use dirverify::jsonl::{stream_jsonl, JsonlWriter};
+use dirverify::{algorithm_name, ChecksumEntry, HashAlgorithm, OutputFile};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often entries are flushed to the checkpoint file; a crash loses at
+/// most the files hashed since.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A JSONL checksum file that entries are appended to as they are hashed.
+/// Entries left by an earlier, interrupted run are loaded when it is
+/// opened, so their files need not be read again.
+pub struct Checkpoint {
+    path: PathBuf,
+    writer: JsonlWriter<BufWriter<File>>,
+    recorded: HashMap<String, ChecksumEntry>,
+    last_flush: Mutex<Instant>,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path`, creating it if it doesn't exist. An
+    /// existing one must come from a run with the same `algorithm` and
+    /// `hash_includes_name`.
+    pub fn open(
+        path: &Path,
+        algorithm: HashAlgorithm,
+        hash_includes_name: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let recorded = match File::open(path) {
+            // A run killed before writing anything leaves an empty file
+            Ok(file) if file.metadata()?.len() > 0 => {
+                load(BufReader::new(file), algorithm, hash_includes_name)?
+            }
+            Ok(_) => HashMap::new(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Rewrite what was recovered, so a torn last line doesn't end up in
+        // the middle of the file once more entries are appended
+        let mut output = OutputFile::create(path, true)?;
+        let writer = JsonlWriter::new(&mut output, algorithm, hash_includes_name, None)?;
+        for entry in recorded.values() {
+            writer.write_entry(entry);
+        }
+        writer.finish()?;
+        output.commit()?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Checkpoint {
+            path: path.to_path_buf(),
+            writer: JsonlWriter::append(BufWriter::new(file)),
+            recorded,
+            last_flush: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Entries recorded by earlier runs, keyed by path.
+    pub fn recorded(&self) -> &HashMap<String, ChecksumEntry> {
+        &self.recorded
+    }
+
+    /// Appends `entry` unless an earlier run already recorded it, flushing
+    /// every [`FLUSH_INTERVAL`].
+    pub fn record(&self, entry: &ChecksumEntry) {
+        let known = self.recorded.get(&entry.path).is_some_and(|recorded| {
+            recorded.hash == entry.hash
+                && recorded.size == entry.size
+                && recorded.modified == entry.modified
+        });
+        if !known {
+            self.writer.write_entry(entry);
+        }
+        let mut last_flush = self.last_flush.lock().unwrap();
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush();
+            *last_flush = Instant::now();
+        }
+    }
+
+    /// Flushes the remaining entries and keeps the file for the next run.
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish().map(drop)
+    }
+
+    /// Deletes the checkpoint once the output it stood in for is written.
+    pub fn remove(self) -> io::Result<()> {
+        let Checkpoint { path, writer, .. } = self;
+        drop(writer.finish()?);
+        fs::remove_file(path)
+    }
+}
+
+/// Reads the entries of an existing checkpoint, keeping the last one seen
+/// for each path.
+fn load(
+    reader: impl BufRead,
+    algorithm: HashAlgorithm,
+    hash_includes_name: bool,
+) -> Result<HashMap<String, ChecksumEntry>, Box<dyn Error>> {
+    let (header, entries) = stream_jsonl(reader)?;
+    let algorithm = algorithm_name(algorithm);
+    if header.algorithm != algorithm {
+        return Err(format!(
+            "checkpoint uses {} but this run uses {}",
+            header.algorithm, algorithm
+        )
+        .into());
+    }
+    if header.hash_includes_name.unwrap_or(false) != hash_includes_name {
+        return Err("checkpoint and this run disagree on --hash-includes-name".into());
+    }
+    // A run killed mid-write can leave a torn last line; what precedes it
+    // is still good
+    Ok(entries
+        .map_while(Result::ok)
+        .map(|entry| (entry.path.clone(), entry))
+        .collect())
+}
@@ -0,0 +1,53 @@
+//! Colored status labels for verify output on terminals.
+
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When status labels are colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when stderr is a terminal and NO_COLOR is unset or empty
+    Auto,
+    Always,
+    Never,
+}
+
+/// Set by [`init`] before any output is written.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decides once whether [`paint`] adds escape codes. `Auto` follows the
+/// NO_COLOR convention (https://no-color.org); an explicit choice wins over it.
+pub fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            io::stderr().is_terminal() && !no_color
+        }
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Green,
+    Red,
+    Yellow,
+}
+
+/// `label` in `color`, or as it is when colors are off.
+pub fn paint(label: &str, color: Color) -> Cow<'_, str> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Cow::Borrowed(label);
+    }
+    let code = match color {
+        Color::Green => 32,
+        Color::Red => 31,
+        Color::Yellow => 33,
+    };
+    Cow::Owned(format!("\x1b[{}m{}\x1b[0m", code, label))
+}
@@ -0,0 +1,409 @@
+use crate::hashing::{from_hex, to_hex};
+use crate::{parse_algorithm, ChecksumEntry, EntryKind, ASSUMED_UNCHANGED_HASH};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// On-disk layout of a checksum file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// dirverify's own JSON document
+    Json,
+    /// `<hash>  <path>` lines, as written by sha256sum and friends
+    Coreutils,
+    /// `ALGO (path) = hash` lines, as written by BSD md5 and shasum --tag
+    Bsd,
+    /// A header line, then one JSON entry per line, written while hashing
+    Jsonl,
+    /// `sha256-<base64>  <path>` lines with Subresource Integrity values
+    Sri,
+    /// A `path,hash,size,modified` header, then one row per file, for
+    /// spreadsheets and databases
+    Csv,
+}
+
+/// Header row written by [`to_csv`].
+pub const CSV_HEADER: [&str; 4] = ["path", "hash", "size", "modified"];
+
+/// Algorithms that Subresource Integrity allows.
+pub const SRI_ALGORITHMS: [&str; 3] = ["sha256", "sha384", "sha512"];
+
+/// Picks the format of an existing checksum file. An explicit non-default
+/// `requested` format wins; otherwise names like `sums.sha256` or
+/// `SHA256SUMS` are treated as coreutils files, `*.jsonl` as JSONL and
+/// `*.csv` as CSV.
+pub fn detect(path: &Path, requested: ManifestFormat) -> ManifestFormat {
+    let has_extension = |extension: &str| {
+        path.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+    };
+    if requested != ManifestFormat::Json {
+        requested
+    } else if algorithm_from_name(path).is_some() {
+        ManifestFormat::Coreutils
+    } else if has_extension("jsonl") {
+        ManifestFormat::Jsonl
+    } else if has_extension("csv") {
+        ManifestFormat::Csv
+    } else {
+        ManifestFormat::Json
+    }
+}
+
+/// Guesses the format from the first non-empty line of a checksum file
+/// whose name doesn't reveal it. Anything unrecognized is left to the JSON
+/// reader, which reports what is wrong with it.
+pub fn sniff(first_line: &str) -> ManifestFormat {
+    let line = first_line.trim();
+    if line.starts_with('{') {
+        // A JSONL header is a whole object on one line without entries
+        let header = serde_json::from_str::<serde_json::Value>(line).ok();
+        if header.is_some_and(|header| header.is_object() && header.get("entries").is_none()) {
+            ManifestFormat::Jsonl
+        } else {
+            ManifestFormat::Json
+        }
+    } else if line.starts_with("path,hash") {
+        ManifestFormat::Csv
+    } else if parse_bsd(line).is_ok() {
+        ManifestFormat::Bsd
+    } else if parse_sri(line).is_ok() {
+        ManifestFormat::Sri
+    } else if parse_coreutils(line).is_ok_and(|entries| {
+        // --assume-unchanged writes a placeholder in place of the digest
+        entries.iter().all(|e| {
+            e.hash == ASSUMED_UNCHANGED_HASH || e.hash.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+    }) {
+        ManifestFormat::Coreutils
+    } else {
+        ManifestFormat::Json
+    }
+}
+
+/// Returns the algorithm implied by a coreutils-style file name, if any.
+pub fn algorithm_from_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let candidate = match name.strip_suffix("sums") {
+        Some(stem) => stem.to_string(),
+        None => path.extension()?.to_string_lossy().to_lowercase(),
+    };
+    parse_algorithm(&candidate).map(|_| candidate)
+}
+
+/// Renders entries as GNU coreutils checksum lines.
+///
+/// Like GNU tools, a line whose path contains a backslash, newline or
+/// carriage return starts with `\` and has those characters escaped. Only
+/// regular files are written, since the format can't express symlinks.
+pub fn to_coreutils(entries: &[ChecksumEntry]) -> String {
+    let mut output = String::new();
+    for entry in entries.iter().filter(|entry| entry.kind.is_file()) {
+        let (marker, path) = escape(&entry.path);
+        output.push_str(&format!("{}{}  {}\n", marker, entry.hash, path));
+    }
+    output
+}
+
+/// Like [`to_coreutils`], but ends each record with a NUL byte instead of a
+/// newline, as `sha256sum -z` does. Paths are written unescaped, newlines
+/// and all, since no path can hold a NUL.
+pub fn to_coreutils_null(entries: &[ChecksumEntry]) -> String {
+    let mut output = String::new();
+    for entry in entries.iter().filter(|entry| entry.kind.is_file()) {
+        output.push_str(&format!("{}  {}\0", entry.hash, entry.path));
+    }
+    output
+}
+
+/// Renders entries as BSD-style tagged lines, e.g. `SHA256 (path) = hash`.
+/// Paths are escaped and symlinks left out the same way as in
+/// [`to_coreutils`].
+pub fn to_bsd(algorithm: &str, entries: &[ChecksumEntry]) -> String {
+    let tag = algorithm.to_uppercase();
+    let mut output = String::new();
+    for entry in entries.iter().filter(|entry| entry.kind.is_file()) {
+        let (marker, path) = escape(&entry.path);
+        output.push_str(&format!("{}{} ({}) = {}\n", marker, tag, path, entry.hash));
+    }
+    output
+}
+
+/// Renders entries as Subresource Integrity values followed by their path,
+/// e.g. `sha384-<base64>  app.js`, ready for an `integrity` attribute.
+/// Paths are escaped and symlinks left out as in [`to_coreutils`].
+pub fn to_sri(algorithm: &str, entries: &[ChecksumEntry]) -> Result<String, String> {
+    if !SRI_ALGORITHMS.contains(&algorithm) {
+        return Err(format!(
+            "SRI only supports {}, not {}",
+            SRI_ALGORITHMS.join(", "),
+            algorithm
+        ));
+    }
+    let mut output = String::new();
+    for entry in entries.iter().filter(|entry| entry.kind.is_file()) {
+        let digest = from_hex(&entry.hash)
+            .ok_or_else(|| format!("{}: '{}' is not a hex digest", entry.path, entry.hash))?;
+        let (marker, path) = escape(&entry.path);
+        output.push_str(&format!(
+            "{}{}-{}  {}\n",
+            marker,
+            algorithm,
+            BASE64.encode(digest),
+            path
+        ));
+    }
+    Ok(output)
+}
+
+/// Renders entries as CSV rows under [`CSV_HEADER`]. Fields holding commas,
+/// quotes or line breaks are quoted; a missing size or modification time is
+/// left empty. Only regular files are written, as in [`to_coreutils`].
+pub fn to_csv(entries: &[ChecksumEntry]) -> Result<String, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_HEADER).map_err(|e| e.to_string())?;
+    for entry in entries.iter().filter(|entry| entry.kind.is_file()) {
+        let number = |value: Option<u64>| value.map(|n| n.to_string()).unwrap_or_default();
+        writer
+            .write_record([
+                entry.path.as_str(),
+                entry.hash.as_str(),
+                &number(entry.size),
+                &number(entry.modified),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
+
+/// Escapes a path for a checksum line, returning the `\` line marker to use.
+fn escape(path: &str) -> (&'static str, String) {
+    let escaped = path
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    let marker = if escaped != path { "\\" } else { "" };
+    (marker, escaped)
+}
+
+/// Parses GNU coreutils checksum lines, accepting both text (`  `) and
+/// binary (` *`) mode separators.
+pub fn parse_coreutils(text: &str) -> Result<Vec<ChecksumEntry>, String> {
+    parse_coreutils_records(text.lines(), false)
+}
+
+/// Parses NUL-terminated records as written by [`to_coreutils_null`] or
+/// `sha256sum -z`. Paths are taken as they are, without unescaping.
+pub fn parse_coreutils_null(text: &str) -> Result<Vec<ChecksumEntry>, String> {
+    parse_coreutils_records(text.split('\0'), true)
+}
+
+fn parse_coreutils_records<'a>(
+    records: impl Iterator<Item = &'a str>,
+    null: bool,
+) -> Result<Vec<ChecksumEntry>, String> {
+    let unit = if null { "record" } else { "line" };
+    let mut entries = Vec::new();
+    for (index, line) in records.enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || format!("{} {}: not a '<hash>  <path>' line", unit, index + 1);
+
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) if !null => (true, rest),
+            _ => (false, line),
+        };
+        let (hash, rest) = line.split_once(' ').ok_or_else(malformed)?;
+        let path = rest
+            .strip_prefix(' ')
+            .or_else(|| rest.strip_prefix('*'))
+            .filter(|path| !hash.is_empty() && !path.is_empty())
+            .ok_or_else(malformed)?;
+        let path = if escaped {
+            unescape(path).ok_or_else(malformed)?
+        } else {
+            path.to_string()
+        };
+
+        entries.push(entry(path, hash));
+    }
+    Ok(entries)
+}
+
+/// Parses BSD-style tagged lines, returning the algorithm named by the tags
+/// along with the entries. All lines must use the same algorithm.
+pub fn parse_bsd(text: &str) -> Result<(String, Vec<ChecksumEntry>), String> {
+    let mut algorithm: Option<String> = None;
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || format!("line {}: not an 'ALGO (path) = hash' line", index + 1);
+
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (tag, rest) = line.split_once(" (").ok_or_else(malformed)?;
+        let (path, hash) = rest.rsplit_once(") = ").ok_or_else(malformed)?;
+        if path.is_empty() || hash.is_empty() {
+            return Err(malformed());
+        }
+        let path = if escaped {
+            unescape(path).ok_or_else(malformed)?
+        } else {
+            path.to_string()
+        };
+
+        let tag = tag.to_lowercase();
+        if parse_algorithm(&tag).is_none() {
+            return Err(format!("line {}: unknown algorithm '{}'", index + 1, tag));
+        }
+        match &algorithm {
+            Some(known) if *known != tag => {
+                return Err(format!(
+                    "line {}: algorithm '{}' differs from '{}' used earlier",
+                    index + 1,
+                    tag,
+                    known
+                ));
+            }
+            Some(_) => {}
+            None => algorithm = Some(tag),
+        }
+
+        entries.push(entry(path, hash));
+    }
+    let algorithm = algorithm.ok_or("no checksum lines found")?;
+    Ok((algorithm, entries))
+}
+
+/// Parses lines written by [`to_sri`], returning their algorithm and the
+/// entries with hex hashes. All lines must use the same algorithm.
+pub fn parse_sri(text: &str) -> Result<(String, Vec<ChecksumEntry>), String> {
+    let mut algorithm: Option<String> = None;
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let malformed = || format!("line {}: not an 'algo-base64  path' line", index + 1);
+
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (integrity, path) = line.split_once("  ").ok_or_else(malformed)?;
+        let (tag, digest) = integrity.split_once('-').ok_or_else(malformed)?;
+        let digest = BASE64.decode(digest).map_err(|_| malformed())?;
+        if path.is_empty() {
+            return Err(malformed());
+        }
+        let path = if escaped {
+            unescape(path).ok_or_else(malformed)?
+        } else {
+            path.to_string()
+        };
+
+        if !SRI_ALGORITHMS.contains(&tag) {
+            return Err(format!("line {}: unknown algorithm '{}'", index + 1, tag));
+        }
+        match &algorithm {
+            Some(known) if known != tag => {
+                return Err(format!(
+                    "line {}: algorithm '{}' differs from '{}' used earlier",
+                    index + 1,
+                    tag,
+                    known
+                ));
+            }
+            Some(_) => {}
+            None => algorithm = Some(tag.to_string()),
+        }
+
+        entries.push(entry(path, &to_hex(&digest)));
+    }
+    let algorithm = algorithm.ok_or("no SRI lines found")?;
+    Ok((algorithm, entries))
+}
+
+/// Parses rows written by [`to_csv`]. Columns are found by their header, so
+/// they may come in any order and extra ones are ignored; `path` and `hash`
+/// are required, and empty `size` or `modified` fields are left unset.
+pub fn parse_csv(text: &str) -> Result<Vec<ChecksumEntry>, String> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (Some(path_column), Some(hash_column)) = (column("path"), column("hash")) else {
+        return Err("the CSV header needs 'path' and 'hash' columns".to_string());
+    };
+    let (size_column, modified_column) = (column("size"), column("modified"));
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let line = record.position().map_or(0, |position| position.line());
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .filter(|field| !field.is_empty())
+        };
+        let number = |column: Option<usize>, name: &str| {
+            field(column)
+                .map(|value| {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| format!("line {}: {} '{}' is not a number", line, name, value))
+                })
+                .transpose()
+        };
+        let (Some(path), Some(hash)) = (field(Some(path_column)), field(Some(hash_column))) else {
+            return Err(format!("line {}: path and hash must not be empty", line));
+        };
+
+        let mut entry = entry(path.to_string(), hash);
+        entry.size = number(size_column, "size")?;
+        entry.modified = number(modified_column, "modified")?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn entry(path: String, hash: &str) -> ChecksumEntry {
+    ChecksumEntry {
+        path,
+        hash: hash.to_lowercase(),
+        algorithm: None,
+        hashes: None,
+        kind: EntryKind::File,
+        link_target: None,
+        modified: None,
+        size: None,
+        ads: None,
+        caps: None,
+        mode: None,
+        text: false,
+    }
+}
+
+fn unescape(path: &str) -> Option<String> {
+    let mut result = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => result.push('\\'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
@@ -0,0 +1,342 @@
+use crate::hashing::{hash_entry_multi, key_check, link_target, HashAlgorithm, HashOptions};
+use crate::manifest::{
+    algorithm_name, ChecksumEntry, ChecksumFile, EntryKind, ASSUMED_UNCHANGED_HASH,
+};
+use crate::mode::file_mode;
+use crate::walk::{is_special, matches_any, relative_path, walk_files, WalkOptions};
+use crate::{ads, caps};
+use glob::Pattern;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+/// Settings for generating checksum entries.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Directory to scan; stored paths are relative to it
+    pub directory: PathBuf,
+    pub walk: WalkOptions,
+    pub algorithm: HashAlgorithm,
+    /// Further algorithms hashed in the same read as `algorithm`, stored in
+    /// each entry's `hashes`
+    pub extra_algorithms: Vec<HashAlgorithm>,
+    /// Record modification times, as needed for skip-newer verification.
+    /// Sizes are always recorded.
+    pub record_modified: bool,
+    pub record_ads: bool,
+    pub record_caps: bool,
+    /// Record Unix permission bits (ignored on other platforms)
+    pub record_mode: bool,
+    /// Mix each file's relative path into its hash
+    pub hash_includes_name: bool,
+    /// Files whose relative path matches are recorded without being hashed
+    pub assume_unchanged: Vec<Pattern>,
+    /// Files whose relative path matches are hashed with carriage returns
+    /// removed and marked [`ChecksumEntry::text`]
+    pub text_normalize: Vec<Pattern>,
+    /// Entries from an earlier run, keyed by path. A file whose size and
+    /// modification time (to the second) still match its entry keeps the
+    /// recorded hash instead of being read again.
+    pub previous: HashMap<String, ChecksumEntry>,
+    pub hash: HashOptions,
+}
+
+/// Totals for a hashing run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashStats {
+    /// Bytes read and hashed
+    pub bytes: u64,
+    /// Files whose contents were read and hashed
+    pub hashed: usize,
+    /// Files that kept their hash from [`GenerateOptions::previous`]
+    pub reused: usize,
+}
+
+impl GenerateOptions {
+    /// Options that hash every file under `directory` with `algorithm`.
+    pub fn new(directory: impl Into<PathBuf>, algorithm: HashAlgorithm) -> Self {
+        GenerateOptions {
+            directory: directory.into(),
+            walk: WalkOptions::default(),
+            algorithm,
+            extra_algorithms: Vec::new(),
+            record_modified: false,
+            record_ads: false,
+            record_caps: false,
+            record_mode: false,
+            hash_includes_name: false,
+            assume_unchanged: Vec::new(),
+            text_normalize: Vec::new(),
+            previous: HashMap::new(),
+            hash: HashOptions::default(),
+        }
+    }
+}
+
+/// Walks `options.directory` and hashes every selected file, starting on
+/// each as soon as the walk finds it. Fails if any file could not be hashed;
+/// use [`hash_files`] to keep partial results.
+pub fn generate(options: &GenerateOptions) -> Result<ChecksumFile, Box<dyn Error>> {
+    let errors = Mutex::new(Vec::new());
+    let (entries, _) = hash_files(
+        walked_files(&options.directory, &options.walk),
+        |path| relative_path(path, &options.directory),
+        options,
+        |path, result| {
+            if let Err(e) = result {
+                errors
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}: {}", path.display(), e));
+            }
+        },
+    );
+
+    let errors = errors.into_inner().unwrap();
+    if let Some(first) = errors.first() {
+        return Err(format!(
+            "{} files could not be hashed, first: {}",
+            errors.len(),
+            first
+        )
+        .into());
+    }
+    let mut file = ChecksumFile::new(options.algorithm, options.hash_includes_name, entries);
+    file.key_check = options
+        .hash
+        .key
+        .as_deref()
+        .map(|key| key_check(key, options.algorithm));
+    file.set_encoding(options.hash.encoding);
+    Ok(file)
+}
+
+/// Hashes `files` in parallel, storing each under the path returned by
+/// `relative_path`. `files` may be a slice or a parallel iterator such as
+/// [`walked_files`]. `on_result` sees every outcome as it happens; files that
+/// fail are left out of the returned entries. Also returns the run's totals.
+pub fn hash_files<I, P, R, F>(
+    files: I,
+    relative_path: R,
+    options: &GenerateOptions,
+    on_result: F,
+) -> (Vec<ChecksumEntry>, HashStats)
+where
+    I: IntoParallelIterator<Item = P>,
+    P: AsRef<Path>,
+    R: Fn(&Path) -> String + Sync,
+    F: Fn(&Path, &Result<ChecksumEntry, String>) + Sync,
+{
+    let entries = Mutex::new(Vec::new());
+    let stats = stream_hashes(files, relative_path, options, |path, result| {
+        on_result(path, &result);
+        if let Ok(entry) = result {
+            entries.lock().unwrap().push(entry);
+        }
+    });
+    (entries.into_inner().unwrap(), stats)
+}
+
+/// Like [`hash_files`], but hands each result to `on_result` instead of
+/// keeping it, so memory use doesn't grow with the number of files. Returns
+/// the run's totals.
+pub fn stream_hashes<I, P, R, F>(
+    files: I,
+    relative_path: R,
+    options: &GenerateOptions,
+    on_result: F,
+) -> HashStats
+where
+    I: IntoParallelIterator<Item = P>,
+    P: AsRef<Path>,
+    R: Fn(&Path) -> String + Sync,
+    F: Fn(&Path, Result<ChecksumEntry, String>) + Sync,
+{
+    let bytes = AtomicU64::new(0);
+    let hashed = AtomicUsize::new(0);
+    let reused = AtomicUsize::new(0);
+    files.into_par_iter().for_each(|path| {
+        let path = path.as_ref();
+        let relative_path = relative_path(path);
+        let result = process_file(
+            path,
+            &relative_path,
+            matches_any(&relative_path, &options.assume_unchanged),
+            options,
+        )
+        .map(|(entry, stats)| {
+            bytes.fetch_add(stats.bytes, Ordering::Relaxed);
+            hashed.fetch_add(stats.hashed, Ordering::Relaxed);
+            reused.fetch_add(stats.reused, Ordering::Relaxed);
+            entry
+        })
+        .map_err(|e| e.to_string());
+        on_result(path, result);
+    });
+    HashStats {
+        bytes: bytes.into_inner(),
+        hashed: hashed.into_inner(),
+        reused: reused.into_inner(),
+    }
+}
+
+/// Walks `directory` on a background thread, yielding files to rayon as
+/// they are found so hashing overlaps the walk. Files come in no particular
+/// order.
+pub fn walked_files(
+    directory: &Path,
+    options: &WalkOptions,
+) -> impl ParallelIterator<Item = PathBuf> {
+    let (sender, receiver) = mpsc::channel();
+    let directory = directory.to_path_buf();
+    let options = options.clone();
+    thread::spawn(move || {
+        walk_files(&directory, &options, |path| {
+            // The receiver only goes away once hashing has stopped
+            let _ = sender.send(path);
+        })
+    });
+    receiver.into_iter().par_bridge()
+}
+
+/// Whether `entry` holds digests for exactly `algorithms` besides its main
+/// hash, so reusing it keeps every requested digest.
+fn has_extra_hashes(entry: &ChecksumEntry, algorithms: &[HashAlgorithm]) -> bool {
+    let recorded = entry.hashes.as_ref().map_or(0, BTreeMap::len);
+    recorded == algorithms.len()
+        && algorithms.iter().all(|&algorithm| {
+            entry
+                .hashes
+                .as_ref()
+                .is_some_and(|hashes| hashes.contains_key(&algorithm_name(algorithm)))
+        })
+}
+
+/// Builds the entry for one file, along with what it took to hash it.
+fn process_file(
+    path: &Path,
+    relative_path: &str,
+    assume_unchanged: bool,
+    options: &GenerateOptions,
+) -> Result<(ChecksumEntry, HashStats), Box<dyn Error>> {
+    if !options.walk.follow_symlinks && path.is_symlink() {
+        let target = link_target(path)?;
+        let entry = ChecksumEntry {
+            path: relative_path.to_string(),
+            hash: target.clone(),
+            algorithm: None,
+            hashes: None,
+            kind: EntryKind::Symlink,
+            link_target: Some(target),
+            modified: None,
+            size: None,
+            ads: None,
+            caps: None,
+            mode: None,
+            text: false,
+        };
+        return Ok((entry, HashStats::default()));
+    }
+    if path.is_dir() {
+        let entry = ChecksumEntry {
+            path: relative_path.to_string(),
+            hash: String::new(),
+            algorithm: None,
+            hashes: None,
+            kind: EntryKind::Directory,
+            link_target: None,
+            modified: None,
+            size: None,
+            ads: None,
+            caps: None,
+            mode: None,
+            text: false,
+        };
+        return Ok((entry, HashStats::default()));
+    }
+
+    // Lists that bypass the walk, or a file replaced since, could still
+    // name a FIFO, which would block the thread on open
+    if !options.walk.allow_special && is_special(path, true) {
+        return Err("skipped: not a regular file".into());
+    }
+
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let text = !assume_unchanged && matches_any(relative_path, &options.text_normalize);
+    let previous = options.previous.get(relative_path).filter(|previous| {
+        !assume_unchanged
+            && previous.kind.is_file()
+            && previous.text == text
+            && previous.algorithm.is_none()
+            && previous.hash != ASSUMED_UNCHANGED_HASH
+            && previous.size == Some(metadata.len())
+            && previous.modified == Some(mtime)
+            && has_extra_hashes(previous, &options.extra_algorithms)
+    });
+
+    let mut stats = HashStats::default();
+    let (hash, hashes) = if assume_unchanged {
+        (ASSUMED_UNCHANGED_HASH.to_string(), None)
+    } else if let Some(previous) = previous {
+        stats.reused = 1;
+        (previous.hash.clone(), previous.hashes.clone())
+    } else {
+        stats.hashed = 1;
+        stats.bytes = metadata.len();
+        let mut algorithms = vec![options.algorithm];
+        algorithms.extend(&options.extra_algorithms);
+        let hash_options = HashOptions {
+            normalize_text: text,
+            ..options.hash.clone()
+        };
+        let mut digests = hash_entry_multi(
+            path,
+            relative_path,
+            &algorithms,
+            options.hash_includes_name,
+            &hash_options,
+        )?
+        .into_iter();
+        let (_, hash) = digests.next().expect("one digest per algorithm");
+        let hashes: BTreeMap<String, String> = digests
+            .map(|(algorithm, digest)| (algorithm_name(algorithm), digest))
+            .collect();
+        (hash, (!hashes.is_empty()).then_some(hashes))
+    };
+    let ads = match previous.and_then(|previous| previous.ads.clone()) {
+        _ if !options.record_ads || assume_unchanged => None,
+        Some(ads) => Some(ads),
+        None => ads::hash_streams(path, options.algorithm, &options.hash)?,
+    };
+    let caps = if options.record_caps {
+        caps::read_capabilities(path)?
+    } else {
+        None
+    };
+
+    let entry = ChecksumEntry {
+        path: relative_path.to_string(),
+        hash,
+        algorithm: None,
+        hashes,
+        kind: EntryKind::File,
+        link_target: None,
+        modified: options.record_modified.then_some(mtime),
+        size: Some(metadata.len()),
+        ads,
+        caps,
+        mode: options.record_mode.then(|| file_mode(&metadata)).flatten(),
+        text,
+    };
+    Ok((entry, stats))
+}
@@ -1,80 +1,804 @@
+use crate::manifest::algorithm_name;
+use crate::prefetch::PrefetchReader;
+use crate::readahead::ReadAheadReader;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use blake2::{Blake2s256, Digest as Blake2Digest};
+use clap::ValueEnum;
 use crc32fast::Hasher as Crc32Hasher;
-use md5;
-use sha2::Sha256;
-use std::fs::File;
+use hmac::{Hmac, Mac};
+use memmap2::Mmap;
+use sha2::{Sha256, Sha384, Sha512};
+use std::fs::{self, File};
 use std::io::{self, Read};
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
 use xxhash_rust::xxh3::Xxh3;
+use xxhash_rust::xxh64::Xxh64;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashAlgorithm {
     Sha256,
+    Sha384,
+    Sha512,
     Md5,
     Crc32,
     Blake2,
+    Blake3,
     Xxh3,
+    Xxh64,
 }
 
-pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
-    let mut file = File::open(path)?;
-    let mut buffer = vec![0; 65536]; // 64KB buffer
+impl HashAlgorithm {
+    /// Whether finding two files with the same hash practically implies
+    /// identical content. CRC32, XXH3 and XXH64 collide far too easily for that.
+    pub fn is_cryptographic(self) -> bool {
+        !matches!(
+            self,
+            HashAlgorithm::Crc32 | HashAlgorithm::Xxh3 | HashAlgorithm::Xxh64
+        )
+    }
+
+    /// Whether [`HashOptions::key`] can turn this algorithm into an HMAC.
+    pub fn supports_hmac(self) -> bool {
+        matches!(
+            self,
+            HashAlgorithm::Sha256 | HashAlgorithm::Sha384 | HashAlgorithm::Sha512
+        )
+    }
+}
+
+/// How digests are written as text in checksum files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HashEncoding {
+    /// Lowercase hexadecimal
+    #[default]
+    Hex,
+    /// Standard base64 with padding, about a third shorter than hex
+    Base64,
+}
+
+impl HashEncoding {
+    /// The name stored in a checksum file's `encoding` field.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashEncoding::Hex => "hex",
+            HashEncoding::Base64 => "base64",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(HashEncoding::Hex),
+            "base64" => Some(HashEncoding::Base64),
+            _ => None,
+        }
+    }
+
+    pub fn encode(self, digest: &[u8]) -> String {
+        match self {
+            HashEncoding::Hex => to_hex(digest),
+            HashEncoding::Base64 => BASE64.encode(digest),
+        }
+    }
+
+    pub fn decode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            HashEncoding::Hex => from_hex(text),
+            HashEncoding::Base64 => BASE64.decode(text).ok(),
+        }
+    }
 
-    match algorithm {
-        HashAlgorithm::Sha256 => {
-            let mut hasher = Sha256::new();
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
+    /// Digest bits carried by each character.
+    pub fn bits_per_char(self) -> usize {
+        match self {
+            HashEncoding::Hex => 4,
+            HashEncoding::Base64 => 6,
+        }
+    }
+}
+
+/// Files smaller than this are always read sequentially, even with prefetch.
+const PREFETCH_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// Files smaller than this are read normally, even with mmap.
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Read buffer size used unless [`HashOptions::buffer_size`] says otherwise.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files hashed with more algorithms than this have their hashers updated
+/// side by side on the [`algorithm_pool`] instead of one after another.
+const SERIAL_ALGORITHMS: usize = 2;
+
+/// Tuning knobs for how file contents are read and hashed.
+#[derive(Debug, Clone)]
+pub struct HashOptions {
+    /// Number of concurrent range reads issued ahead of the hasher (0 = off)
+    pub prefetch: usize,
+    /// Bytes read per call into the hasher; must be non-zero
+    pub buffer_size: usize,
+    /// Memory-map large regular files and hash them in one pass
+    pub mmap: bool,
+    /// Size of a separate thread pool that reads files ahead of the hashing
+    /// threads (0 = read on the hashing thread)
+    pub read_threads: usize,
+    /// Give up on a file whose hashing takes longer than this
+    pub timeout: Option<Duration>,
+    /// Secret that turns SHA-256/384/512 into HMACs, so hashes can't be
+    /// forged without it; other algorithms fail when a key is set
+    pub key: Option<Arc<[u8]>>,
+    /// How [`hash_file`] and [`hash_entry`] render digests
+    pub encoding: HashEncoding,
+    /// Times to retry a file whose read fails with an error that may be
+    /// transient, waiting longer before each attempt
+    pub retries: u32,
+    /// Files that needed at least one retry, shared by every clone of
+    /// these options
+    pub retried: Arc<AtomicUsize>,
+    /// Drop every carriage return before hashing, so text files hash the
+    /// same with CRLF and LF line endings. Such digests won't match tools
+    /// that hash the bytes as stored.
+    pub normalize_text: bool,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        HashOptions {
+            prefetch: 0,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            mmap: false,
+            read_threads: 0,
+            timeout: None,
+            key: None,
+            encoding: HashEncoding::Hex,
+            retries: 0,
+            retried: Arc::new(AtomicUsize::new(0)),
+            normalize_text: false,
+        }
+    }
+}
+
+/// Hashes the file at `path`, returning the digest in `options.encoding`.
+pub fn hash_file(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    options: &HashOptions,
+) -> io::Result<String> {
+    hash_file_raw(path, algorithm, options).map(|digest| options.encoding.encode(&digest))
+}
+
+/// Hashes the file at `path`, returning the raw digest bytes.
+pub fn hash_file_raw(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    options: &HashOptions,
+) -> io::Result<Vec<u8>> {
+    digest_file(path, &[], &[algorithm], options).map(|mut digests| digests.remove(0))
+}
+
+/// Hashes the file at `path` with each of `algorithms`, reading it only
+/// once. Digests are in `options.encoding` and in the order of `algorithms`.
+pub fn hash_file_multi(
+    path: &Path,
+    algorithms: &[HashAlgorithm],
+    options: &HashOptions,
+) -> io::Result<Vec<(HashAlgorithm, String)>> {
+    hash_entry_multi(path, "", algorithms, false, options)
+}
+
+/// Hashes `prefix` followed by the contents of the file at `path`.
+pub fn hash_file_with_prefix(
+    path: &Path,
+    prefix: &[u8],
+    algorithm: HashAlgorithm,
+    options: &HashOptions,
+) -> io::Result<String> {
+    digest_file(path, prefix, &[algorithm], options)
+        .map(|mut digests| options.encoding.encode(&digests.remove(0)))
+}
+
+/// Wait before the first retry of a failed read; it doubles with each
+/// further attempt, up to [`MAX_RETRY_DELAY`].
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Whether a failed read may succeed when tried again. A file that is gone
+/// or can't be opened won't change between attempts, and neither will bad
+/// options; anything else may be a hiccup of a network filesystem.
+fn is_transient(error: &io::Error) -> bool {
+    !matches!(
+        error.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidInput
+    )
+}
+
+/// Raw digests of `prefix` and the file's contents, one per algorithm.
+fn digest_file(
+    path: &Path,
+    prefix: &[u8],
+    algorithms: &[HashAlgorithm],
+    options: &HashOptions,
+) -> io::Result<Vec<Vec<u8>>> {
+    if options.key.is_some() && !algorithms.iter().all(|algorithm| algorithm.supports_hmac()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "HMAC keys only work with sha256, sha384 and sha512",
+        ));
+    }
+    let mut attempt = 0;
+    let mut delay = RETRY_DELAY;
+    loop {
+        let result = match options.timeout {
+            Some(timeout) => hash_with_timeout(path, prefix, algorithms, options, timeout),
+            None => read_and_hash(path, prefix, algorithms, options, None),
+        };
+        match result {
+            Err(e) if attempt < options.retries && is_transient(&e) => {
+                if attempt == 0 {
+                    options.retried.fetch_add(1, Ordering::Relaxed);
                 }
-                hasher.update(&buffer[..bytes_read]);
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                attempt += 1;
             }
-            Ok(format!("{:x}", hasher.finalize()))
-        }
-        HashAlgorithm::Md5 => {
-            let mut context = md5::Context::new();
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                context.consume(&buffer[..bytes_read]);
+            result => return result,
+        }
+    }
+}
+
+/// Hashes on a separate thread and fails with `TimedOut` if that takes
+/// longer than `timeout`, since a read from a stalled network mount can
+/// block forever. The abandoned thread stops at its next read and closes
+/// the file; a read already stuck in the kernel keeps the descriptor open
+/// until the kernel gives up on it.
+fn hash_with_timeout(
+    path: &Path,
+    prefix: &[u8],
+    algorithms: &[HashAlgorithm],
+    options: &HashOptions,
+    timeout: Duration,
+) -> io::Result<Vec<Vec<u8>>> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = channel();
+    let worker = {
+        let path: PathBuf = path.to_path_buf();
+        let prefix = prefix.to_vec();
+        let algorithms = algorithms.to_vec();
+        let options = options.clone();
+        let cancelled = Arc::clone(&cancelled);
+        move || {
+            let result = read_and_hash(&path, &prefix, &algorithms, &options, Some(&cancelled));
+            let _ = sender.send(result);
+        }
+    };
+    thread::spawn(worker);
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {}s", timeout.as_secs_f64()),
+            ))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("hashing thread panicked")),
+    }
+}
+
+/// Opens and hashes the file, stopping with an error once `cancelled` is set.
+fn read_and_hash(
+    path: &Path,
+    prefix: &[u8],
+    algorithms: &[HashAlgorithm],
+    options: &HashOptions,
+    cancelled: Option<&AtomicBool>,
+) -> io::Result<Vec<Vec<u8>>> {
+    let hasher = || {
+        algorithms
+            .iter()
+            .map(|&algorithm| Hasher::new(algorithm, options.key.as_deref()))
+            .collect()
+    };
+    let file = File::open(path)?;
+    if options.mmap && !options.normalize_text {
+        if let Some(digest) = hash_mapped(&file, prefix, hasher())? {
+            return Ok(digest);
+        }
+    }
+    let len = file.metadata()?.len();
+    let file: Box<dyn Read> = if options.prefetch > 0 && len >= PREFETCH_THRESHOLD {
+        Box::new(PrefetchReader::new(file, len, options.prefetch))
+    } else if options.read_threads > 0 {
+        Box::new(ReadAheadReader::new(
+            file,
+            options.buffer_size,
+            options.read_threads,
+        ))
+    } else {
+        Box::new(file)
+    };
+    let file: Box<dyn Read> = if options.normalize_text {
+        Box::new(StripCr { inner: file })
+    } else {
+        file
+    };
+    match cancelled {
+        Some(cancelled) => {
+            let file = Cancellable {
+                inner: file,
+                cancelled,
+            };
+            hash_stream(prefix.chain(file), hasher(), options.buffer_size)
+        }
+        None => hash_stream(prefix.chain(file), hasher(), options.buffer_size),
+    }
+}
+
+/// Fails every read once `cancelled` is set, so an abandoned hash ends and
+/// drops its file instead of reading to the end.
+struct Cancellable<'a, R> {
+    inner: R,
+    cancelled: &'a AtomicBool,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "hash cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Yields the bytes of `inner` without carriage returns.
+struct StripCr<R> {
+    inner: R,
+}
+
+impl<R: Read> Read for StripCr<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.inner.read(buf)?;
+            if read == 0 {
+                return Ok(0);
             }
-            Ok(format!("{:x}", context.compute()))
-        }
-        HashAlgorithm::Crc32 => {
-            let mut hasher = Crc32Hasher::new();
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
+            let mut kept = 0;
+            for index in 0..read {
+                if buf[index] != b'\r' {
+                    buf[kept] = buf[index];
+                    kept += 1;
                 }
-                hasher.update(&buffer[..bytes_read]);
             }
-            Ok(format!("{:08x}", hasher.finalize()))
-        }
-        HashAlgorithm::Blake2 => {
-            let mut hasher = Blake2s256::new();
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..bytes_read]);
+            // A read of nothing but carriage returns isn't the end of input
+            if kept > 0 {
+                return Ok(kept);
             }
-            Ok(format!("{:x}", hasher.finalize()))
-        }
-        HashAlgorithm::Xxh3 => {
-            let mut hasher = Xxh3::new();
-            loop {
-                let bytes_read = file.read(&mut buffer)?;
-                if bytes_read == 0 {
-                    break;
-                }
-                hasher.update(&buffer[..bytes_read]);
+        }
+    }
+}
+
+/// Hashes `prefix` and the mapped contents of `file`, or returns `None` if
+/// the file is too small, not a regular file, or can't be mapped.
+fn hash_mapped(
+    file: &File,
+    prefix: &[u8],
+    mut hashers: Vec<Hasher>,
+) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let metadata = file.metadata()?;
+    if !metadata.is_file() || metadata.len() < MMAP_THRESHOLD {
+        return Ok(None);
+    }
+    // SAFETY: the mapping is only read while `file` is open. If another
+    // process truncates the file meanwhile, reads past the new end fault;
+    // --mmap documents this trade-off.
+    let map = match unsafe { Mmap::map(file) } {
+        Ok(map) => map,
+        Err(_) => return Ok(None),
+    };
+    for hasher in &mut hashers {
+        hasher.update(prefix);
+    }
+    let len = map.len();
+    update_all(&mut hashers, &Arc::new(map), len);
+    Ok(Some(hashers.into_iter().map(Hasher::finalize).collect()))
+}
+
+/// Hashes everything `reader` yields until end of stream, reading
+/// `buffer_size` bytes at a time. Returns the digest as lowercase hex.
+pub fn hash_reader<R: Read>(
+    reader: R,
+    algorithm: HashAlgorithm,
+    buffer_size: usize,
+) -> io::Result<String> {
+    hash_reader_raw(reader, algorithm, buffer_size).map(|digest| to_hex(&digest))
+}
+
+/// Like [`hash_reader`], but returns the raw digest bytes.
+pub fn hash_reader_raw<R: Read>(
+    reader: R,
+    algorithm: HashAlgorithm,
+    buffer_size: usize,
+) -> io::Result<Vec<u8>> {
+    let hashers = vec![Hasher::new(algorithm, None)];
+    hash_stream(reader, hashers, buffer_size).map(|mut digests| digests.remove(0))
+}
+
+/// Feeds everything `reader` yields to each of `hashers` and returns their
+/// digests in the same order.
+fn hash_stream<R: Read>(
+    mut reader: R,
+    mut hashers: Vec<Hasher>,
+    buffer_size: usize,
+) -> io::Result<Vec<Vec<u8>>> {
+    // Shared with the algorithm pool, and read into again once every
+    // hasher is done with the previous chunk
+    let mut buffer = Arc::new(vec![0; buffer_size]);
+    loop {
+        let chunk = Arc::get_mut(&mut buffer).expect("update_all releases the buffer");
+        let bytes_read = reader.read(chunk)?;
+        if bytes_read == 0 {
+            break;
+        }
+        update_all(&mut hashers, &buffer, bytes_read);
+    }
+    Ok(hashers.into_iter().map(Hasher::finalize).collect())
+}
+
+/// Work sent to the [`algorithm_pool`].
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads shared by every file that run hasher updates sent to them, one
+/// per core, or `None` on a single core or if they can't be started.
+/// Unlike a rayon pool, a thread waiting on them doesn't pick up another
+/// file meanwhile, which could nest without bound.
+fn algorithm_pool() -> Option<&'static Mutex<Sender<Job>>> {
+    static POOL: OnceLock<Option<Mutex<Sender<Job>>>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        if threads < 2 {
+            return None;
+        }
+        for index in 0..threads {
+            let receiver = Arc::clone(&receiver);
+            thread::Builder::new()
+                .name(format!("dirverify-algorithm-{}", index))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .ok()?;
+        }
+        Some(Mutex::new(sender))
+    })
+    .as_ref()
+}
+
+/// Feeds the first `len` bytes of `data` to every hasher. With more than
+/// [`SERIAL_ALGORITHMS`] of them, all but the first are updated on the
+/// [`algorithm_pool`] while this thread updates the first, so a slow
+/// algorithm doesn't wait for the rest. Every clone of `data` handed to the
+/// pool is dropped by the time this returns, so the caller can reuse it.
+fn update_all<D>(hashers: &mut Vec<Hasher>, data: &Arc<D>, len: usize)
+where
+    D: AsRef<[u8]> + Send + Sync + 'static,
+{
+    // Checked first so hashing one or two algorithms never starts the pool
+    let pool = if hashers.len() > SERIAL_ALGORITHMS {
+        algorithm_pool()
+    } else {
+        None
+    };
+    let pool = match pool {
+        Some(pool) => pool,
+        None => {
+            for hasher in hashers.iter_mut() {
+                hasher.update(&(**data).as_ref()[..len]);
+            }
+            return;
+        }
+    };
+
+    let (done, updated) = channel();
+    let sender = pool.lock().unwrap().clone();
+    let mut pending = mem::take(hashers).into_iter().enumerate();
+    let (_, mut first) = pending.next().expect("more than one hasher");
+    let mut results: Vec<Option<Hasher>> = vec![None];
+    for (index, mut hasher) in pending {
+        results.push(None);
+        let data = Arc::clone(data);
+        let done = done.clone();
+        let job: Job = Box::new(move || {
+            hasher.update(&(*data).as_ref()[..len]);
+            // Released before the hasher goes back, so the caller's buffer
+            // is unique again once every result is in
+            drop(data);
+            let _ = done.send((index, hasher));
+        });
+        sender.send(job).expect("algorithm pool threads never exit");
+    }
+    drop(done);
+    first.update(&(**data).as_ref()[..len]);
+    results[0] = Some(first);
+    for (index, hasher) in updated {
+        results[index] = Some(hasher);
+    }
+    *hashers = results
+        .into_iter()
+        .map(|hasher| hasher.expect("a pool thread returns every hasher"))
+        .collect();
+}
+
+/// Hashes an in-memory buffer, returning the digest as lowercase hex.
+pub fn hash_bytes(data: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut hasher = Hasher::new(algorithm, None);
+    hasher.update(data);
+    to_hex(&hasher.finalize())
+}
+
+/// HMAC of a fixed message under `key`. Checksum files store it instead of
+/// the key, so verifying with a different key fails up front rather than
+/// as a mismatch on every file.
+pub fn key_check(key: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut hasher = Hasher::new(algorithm, Some(key));
+    hasher.update(b"dirverify key check");
+    to_hex(&hasher.finalize())
+}
+
+/// Digests of `""` and `"abc"` from each algorithm's published test vectors.
+const KNOWN_DIGESTS: [(HashAlgorithm, &str, &str); 9] = [
+    (
+        HashAlgorithm::Sha256,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    ),
+    (
+        HashAlgorithm::Sha384,
+        "38b060a751ac96384cd9327eb1b1e36a21fdb71114be07434c0cc7bf63f6e1da\
+         274edebfe76f65fbd51ad2f14898b95b",
+        "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded1631a8b605a43ff5bed\
+         8086072ba1e7cc2358baeca134c825a7",
+    ),
+    (
+        HashAlgorithm::Sha512,
+        "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+         47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+        "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+         2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+    ),
+    (
+        HashAlgorithm::Md5,
+        "d41d8cd98f00b204e9800998ecf8427e",
+        "900150983cd24fb0d6963f7d28e17f72",
+    ),
+    (HashAlgorithm::Crc32, "00000000", "352441c2"),
+    (
+        HashAlgorithm::Blake2,
+        "69217a3079908094e11121d042354a7c1f55b6482ca1a51e1b250dfd1ed0eef9",
+        "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982",
+    ),
+    (
+        HashAlgorithm::Blake3,
+        "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+        "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+    ),
+    (HashAlgorithm::Xxh3, "2d06800538d394c2", "78af5f94892f3950"),
+    (HashAlgorithm::Xxh64, "ef46db3751d8e999", "44bc2cf5ad770999"),
+];
+
+/// HMACs of `"what do ya want for nothing?"` under the key `"Jefe"`, from
+/// RFC 4231 test case 2.
+const KNOWN_HMACS: [(HashAlgorithm, &str); 3] = [
+    (
+        HashAlgorithm::Sha256,
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+    ),
+    (
+        HashAlgorithm::Sha384,
+        "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e\
+         8e2240ca5e69e2c78b3239ecfab21649",
+    ),
+    (
+        HashAlgorithm::Sha512,
+        "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+         9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+    ),
+];
+
+/// Checks every algorithm against published test vectors, hashing each
+/// input both in one piece and a byte at a time, along with the HMACs.
+/// Returns how many digests were checked, or a line for each one that came
+/// out wrong, as after a dependency upgrade that changed a hasher's output.
+pub fn self_test() -> Result<usize, Vec<String>> {
+    let mut checked = 0;
+    let mut failures = Vec::new();
+    let mut check = |label: String, actual: String, expected: &str| {
+        checked += 1;
+        if actual != expected {
+            failures.push(format!("{}: expected {}, got {}", label, expected, actual));
+        }
+    };
+    for (algorithm, empty, abc) in KNOWN_DIGESTS {
+        let name = algorithm_name(algorithm);
+        check(
+            format!("{} of \"\"", name),
+            hash_bytes(b"", algorithm),
+            empty,
+        );
+        check(
+            format!("{} of \"abc\"", name),
+            hash_bytes(b"abc", algorithm),
+            abc,
+        );
+        let streamed = hash_reader(&b"abc"[..], algorithm, 1).unwrap_or_else(|e| e.to_string());
+        check(format!("{} of \"abc\" streamed", name), streamed, abc);
+    }
+    for (algorithm, expected) in KNOWN_HMACS {
+        let mut hasher = Hasher::new(algorithm, Some(b"Jefe"));
+        hasher.update(b"what do ya want for nothing?");
+        let label = format!("HMAC-{}", algorithm_name(algorithm).to_uppercase());
+        check(label, to_hex(&hasher.finalize()), expected);
+    }
+    if failures.is_empty() {
+        Ok(checked)
+    } else {
+        Err(failures)
+    }
+}
+
+/// A running hash for any supported algorithm.
+pub(crate) enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+    Md5(md5::Context),
+    Crc32(Crc32Hasher),
+    Blake2(Blake2s256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+    Xxh64(Xxh64),
+    HmacSha256(Box<Hmac<Sha256>>),
+    HmacSha384(Box<Hmac<Sha384>>),
+    HmacSha512(Box<Hmac<Sha512>>),
+}
+
+impl Hasher {
+    /// With a `key`, SHA-256/384/512 become HMACs; other algorithms ignore
+    /// it, so callers must check [`HashAlgorithm::supports_hmac`] first.
+    pub(crate) fn new(algorithm: HashAlgorithm, key: Option<&[u8]>) -> Self {
+        // HMAC accepts keys of any length, so new_from_slice can't fail
+        let hmac = "HMAC takes keys of any length";
+        match (algorithm, key) {
+            (HashAlgorithm::Sha256, Some(key)) => {
+                Hasher::HmacSha256(Box::new(Hmac::new_from_slice(key).expect(hmac)))
+            }
+            (HashAlgorithm::Sha384, Some(key)) => {
+                Hasher::HmacSha384(Box::new(Hmac::new_from_slice(key).expect(hmac)))
+            }
+            (HashAlgorithm::Sha512, Some(key)) => {
+                Hasher::HmacSha512(Box::new(Hmac::new_from_slice(key).expect(hmac)))
+            }
+            _ => Hasher::unkeyed(algorithm),
+        }
+    }
+
+    fn unkeyed(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha384 => Hasher::Sha384(Sha384::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Md5 => Hasher::Md5(md5::Context::new()),
+            HashAlgorithm::Crc32 => Hasher::Crc32(Crc32Hasher::new()),
+            HashAlgorithm::Blake2 => Hasher::Blake2(Blake2s256::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+            HashAlgorithm::Xxh64 => Hasher::Xxh64(Xxh64::new(0)),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha384(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Md5(context) => context.consume(data),
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Blake2(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
             }
-            Ok(format!("{:016x}", hasher.digest()))
+            Hasher::Xxh3(hasher) => hasher.update(data),
+            Hasher::Xxh64(hasher) => hasher.update(data),
+            Hasher::HmacSha256(mac) => mac.update(data),
+            Hasher::HmacSha384(mac) => mac.update(data),
+            Hasher::HmacSha512(mac) => mac.update(data),
+        }
+    }
+
+    /// The raw digest; CRC32, XXH3 and XXH64 values are big-endian.
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha384(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(context) => context.compute().to_vec(),
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Hasher::Blake2(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+            Hasher::Xxh64(hasher) => hasher.digest().to_be_bytes().to_vec(),
+            Hasher::HmacSha256(mac) => mac.finalize().into_bytes().to_vec(),
+            Hasher::HmacSha384(mac) => mac.finalize().into_bytes().to_vec(),
+            Hasher::HmacSha512(mac) => mac.finalize().into_bytes().to_vec(),
         }
     }
 }
+
+/// Renders a digest as lowercase hex, the form stored in checksum files.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses a lowercase or uppercase hex digest back into bytes.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The target of the symlink at `path`, recorded in place of a hash for
+/// links that aren't followed.
+pub fn link_target(path: &Path) -> io::Result<String> {
+    Ok(fs::read_link(path)?.to_string_lossy().into_owned())
+}
+
+/// Hashes a file, prefixing its relative path when names are part of the hash.
+pub fn hash_entry(
+    path: &Path,
+    relative_path: &str,
+    algorithm: HashAlgorithm,
+    hash_includes_name: bool,
+    options: &HashOptions,
+) -> io::Result<String> {
+    if hash_includes_name {
+        hash_file_with_prefix(path, relative_path.as_bytes(), algorithm, options)
+    } else {
+        hash_file(path, algorithm, options)
+    }
+}
+
+/// Like [`hash_entry`], but hashes with each of `algorithms` in one read.
+pub fn hash_entry_multi(
+    path: &Path,
+    relative_path: &str,
+    algorithms: &[HashAlgorithm],
+    hash_includes_name: bool,
+    options: &HashOptions,
+) -> io::Result<Vec<(HashAlgorithm, String)>> {
+    let prefix = if hash_includes_name {
+        relative_path.as_bytes()
+    } else {
+        &[]
+    };
+    let digests = digest_file(path, prefix, algorithms, options)?;
+    Ok(algorithms
+        .iter()
+        .zip(digests)
+        .map(|(&algorithm, digest)| (algorithm, options.encoding.encode(&digest)))
+        .collect())
+}
@@ -0,0 +1,256 @@
+//! Newline-delimited JSON checksum files, which can be written and read one
+//! entry at a time instead of as a single document.
+
+use crate::hashing::{to_hex, HashAlgorithm, Hasher};
+use crate::manifest::{algorithm_name, ChecksumEntry, ChecksumFile};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// First line of a JSONL checksum file.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: String,
+    algorithm: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash_includes_name: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    short: Option<usize>,
+}
+
+/// Last line of a JSONL checksum file, written once every entry is in, so
+/// a file cut short can be told apart from a complete one.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Trailer {
+    count: usize,
+    /// See [`ChecksumFile::compute_manifest_hash`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    manifest_hash: Option<String>,
+}
+
+/// Writes a JSONL checksum file: a header line carrying `version` and
+/// `algorithm`, then one line per entry in the order they arrive, then a
+/// trailer with the entry count and manifest hash. Entries can be written
+/// from several threads; the first write error is kept and returned when
+/// finishing.
+pub struct JsonlWriter<W: Write> {
+    writer: Mutex<W>,
+    error: Mutex<Option<io::Error>>,
+    written: AtomicUsize,
+    /// Running manifest hash of the entry lines, unless appending
+    manifest_hash: Mutex<Option<Hasher>>,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    /// Writes the header line to `writer`. `short` is the length hashes
+    /// are cut to with --short, if they are.
+    pub fn new(
+        mut writer: W,
+        algorithm: HashAlgorithm,
+        hash_includes_name: bool,
+        short: Option<usize>,
+    ) -> io::Result<Self> {
+        let header = Header {
+            version: "1.0".to_string(),
+            algorithm: algorithm_name(algorithm),
+            hash_includes_name: hash_includes_name.then_some(true),
+            short,
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        Ok(JsonlWriter {
+            writer: Mutex::new(writer),
+            error: Mutex::new(None),
+            written: AtomicUsize::new(0),
+            manifest_hash: Mutex::new(Some(Hasher::new(algorithm, None))),
+        })
+    }
+
+    /// Writes entries to `writer` without a header, to continue a file that
+    /// already has one.
+    pub fn append(writer: W) -> Self {
+        JsonlWriter {
+            writer: Mutex::new(writer),
+            error: Mutex::new(None),
+            written: AtomicUsize::new(0),
+            manifest_hash: Mutex::new(None),
+        }
+    }
+
+    pub fn write_entry(&self, entry: &ChecksumEntry) {
+        let result = entry_line(entry).map_err(io::Error::from).and_then(|line| {
+            let mut writer = self.writer.lock().unwrap();
+            writer.write_all(&line)?;
+            // Hashed under the writer's lock, so in the order written
+            if let Some(hasher) = self.manifest_hash.lock().unwrap().as_mut() {
+                hasher.update(&line);
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                self.written.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.error.lock().unwrap().get_or_insert(e);
+            }
+        }
+    }
+
+    /// Flushes the entries written so far. A failure is kept like a write
+    /// error and returned by [`JsonlWriter::finish`].
+    pub fn flush(&self) {
+        if let Err(e) = self.writer.lock().unwrap().flush() {
+            self.error.lock().unwrap().get_or_insert(e);
+        }
+    }
+
+    /// Flushes and returns the inner writer, or the first error seen. The
+    /// file is left open-ended, to be continued with [`JsonlWriter::append`].
+    pub fn finish(self) -> io::Result<W> {
+        if let Some(e) = self.error.into_inner().unwrap() {
+            return Err(e);
+        }
+        let mut writer = self.writer.into_inner().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    /// Like [`JsonlWriter::finish`], but first writes the trailer that
+    /// marks the file as complete.
+    pub fn finish_with_trailer(self) -> io::Result<W> {
+        let manifest_hash = self.manifest_hash.lock().unwrap().take();
+        let trailer = Trailer {
+            count: self.written.load(Ordering::Relaxed),
+            manifest_hash: manifest_hash.map(|hasher| to_hex(&hasher.finalize())),
+        };
+        let mut line = serde_json::to_vec(&trailer)?;
+        line.push(b'\n');
+        self.writer.lock().unwrap().write_all(&line)?;
+        self.finish()
+    }
+}
+
+/// Entries of a JSONL checksum file, parsed one line at a time as the
+/// iterator advances. Parse errors name the line they were found on and
+/// end the iteration, as does a file that stops before its trailer or
+/// whose trailer counts a different number of entries.
+pub struct JsonlEntries<R> {
+    lines: io::Lines<R>,
+    line: usize,
+    entries: usize,
+    trailer: Option<Trailer>,
+    ended: bool,
+}
+
+impl<R: BufRead> JsonlEntries<R> {
+    /// Reads up to the next entry, or the error that ends the file.
+    fn read_entry(&mut self) -> Option<Result<ChecksumEntry, Box<dyn Error>>> {
+        loop {
+            let line = match self.lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => {
+                    let truncated =
+                        "Checksum file ends without its trailer line; it may be truncated";
+                    return self.trailer.is_none().then(|| Err(truncated.into()));
+                }
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if self.trailer.is_some() {
+                let error = format!("line {}: entry after the trailer", self.line);
+                return Some(Err(error.into()));
+            }
+            let error = match serde_json::from_str(&line) {
+                Ok(entry) => {
+                    self.entries += 1;
+                    return Some(Ok(entry));
+                }
+                Err(e) => e,
+            };
+            let Ok(trailer) = serde_json::from_str::<Trailer>(&line) else {
+                return Some(Err(format!("line {}: {}", self.line, error).into()));
+            };
+            let count = trailer.count;
+            self.trailer = Some(trailer);
+            if count != self.entries {
+                return Some(Err(format!(
+                    "Checksum file declares {} entries but contains {}; it may be truncated",
+                    count, self.entries
+                )
+                .into()));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlEntries<R> {
+    type Item = Result<ChecksumEntry, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ended {
+            return None;
+        }
+        let entry = self.read_entry();
+        self.ended = !matches!(entry, Some(Ok(_)));
+        entry
+    }
+}
+
+/// Parses the header of a JSONL checksum file and returns it as a checksum
+/// file without entries, along with an iterator that reads the entries
+/// only when asked, so they never all have to be held in memory.
+pub fn stream_jsonl<R: BufRead>(
+    reader: R,
+) -> Result<(ChecksumFile, JsonlEntries<R>), Box<dyn Error>> {
+    let mut lines = reader.lines();
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(|e| format!("header: {}", e))?,
+        None => return Err("empty JSONL checksum file".into()),
+    };
+
+    let file = ChecksumFile {
+        version: header.version,
+        algorithm: header.algorithm,
+        count: None,
+        hash_includes_name: header.hash_includes_name,
+        manifest_hash: None,
+        key_check: None,
+        encoding: None,
+        short: header.short,
+        manifest_hash_in_file_order: true,
+        entries: Vec::new(),
+    };
+    let entries = JsonlEntries {
+        lines,
+        line: 1,
+        entries: 0,
+        trailer: None,
+        ended: false,
+    };
+    Ok((file, entries))
+}
+
+/// Parses a JSONL checksum file line by line. Entries keep their file order.
+pub fn read_jsonl(reader: impl BufRead) -> Result<ChecksumFile, Box<dyn Error>> {
+    let (mut file, mut entries) = stream_jsonl(reader)?;
+    file.entries = entries.by_ref().collect::<Result<_, _>>()?;
+    if let Some(trailer) = entries.trailer {
+        file.count = Some(trailer.count);
+        file.manifest_hash = trailer.manifest_hash;
+    }
+    Ok(file)
+}
+
+/// An entry as written on its own line of a JSONL file, newline included.
+pub(crate) fn entry_line(entry: &ChecksumEntry) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    Ok(line)
+}
@@ -0,0 +1,46 @@
+//! Generate checksum files for directory trees and verify trees against them.
+//!
+//! The `dirverify` binary is a thin command-line wrapper around this crate.
+//!
+//! ```no_run
+//! use dirverify::{generate, verify, GenerateOptions, HashAlgorithm, VerifyOptions};
+//! use std::path::Path;
+//!
+//! let manifest = generate(&GenerateOptions::new("/data", HashAlgorithm::Blake3))?;
+//! let options = VerifyOptions::for_file(&manifest).expect("known algorithm");
+//! let report = verify(&manifest, Path::new("/mnt/backup/data"), &options);
+//! assert!(report.passed());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+mod ads;
+mod caps;
+pub mod compression;
+pub mod formats;
+mod generate;
+pub mod hashing;
+pub mod jsonl;
+mod manifest;
+mod mode;
+mod prefetch;
+mod readahead;
+pub mod remote;
+mod verify;
+mod walk;
+
+pub use caps::Capabilities;
+pub use generate::{generate, hash_files, stream_hashes, walked_files, GenerateOptions, HashStats};
+pub use hashing::{HashAlgorithm, HashEncoding, HashOptions};
+pub use manifest::{
+    algorithm_name, load_checksum_file, load_manifest, load_null_terminated, manifest_format,
+    parse_algorithm, read_manifest, temp_path, write_output, ChecksumEntry, ChecksumFile,
+    EntryKind, ManifestDiff, OutputFile, ASSUMED_UNCHANGED_HASH,
+};
+pub use verify::{
+    find_added, verify, verify_stream, verify_with, VerifyOptions, VerifyReport, VerifyResult,
+    VerifySummary,
+};
+pub use walk::{
+    absolute_in, canonical_output_path, collect_files, compile_patterns, entry_path, is_special,
+    path_relative_to, portable_path, relative_path, walk_files, WalkOptions,
+};
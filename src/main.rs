@@ -1,39 +1,100 @@
 use clap::{Parser, ValueEnum};
+use dirverify::compression::{self, compress, Compression, Encoder};
+use dirverify::formats::{self, ManifestFormat};
+use dirverify::hashing::{
+    self, hash_entry, hash_reader_raw, key_check, link_target, HashEncoding, DEFAULT_BUFFER_SIZE,
+};
+use dirverify::jsonl::{self, JsonlWriter};
+use dirverify::remote;
+use dirverify::{
+    absolute_in, algorithm_name, canonical_output_path, collect_files, compile_patterns,
+    entry_path, find_added, is_special, load_checksum_file, load_manifest, load_null_terminated,
+    manifest_format, parse_algorithm, path_relative_to, portable_path, read_manifest,
+    relative_path, stream_hashes, temp_path, verify_stream, verify_with, walked_files,
+    write_output, ChecksumEntry, ChecksumFile, GenerateOptions, HashAlgorithm, HashOptions,
+    OutputFile, VerifyOptions, VerifyReport, VerifyResult, WalkOptions, ASSUMED_UNCHANGED_HASH,
+};
 use glob::Pattern;
 use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::fs::{self, File};
-use std::io::BufReader;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::SystemTime;
-use walkdir::WalkDir;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-mod hashing;
-use hashing::{hash_file, HashAlgorithm};
+mod checkpoint;
+mod color;
+mod progress;
+use checkpoint::Checkpoint;
+use color::{paint, Color, ColorChoice};
+use progress::{
+    FileCounter, JsonProgress, PercentProgress, PhaseTimer, ProgressFormat, ProgressStream,
+};
+
+/// Set from --quiet before any work starts.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// `eprintln!` for informational messages, which --quiet suppresses.
+/// Warnings, errors and failures are always printed.
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum Algorithm {
     Sha256,
+    Sha384,
+    Sha512,
     Md5,
     Crc32,
     Blake2,
+    Blake3,
     Xxh3,
+    Xxh64,
+    /// The fastest algorithm available, currently xxh3. Not cryptographic:
+    /// it catches accidental corruption, not deliberate tampering
+    Auto,
 }
 
 impl From<Algorithm> for HashAlgorithm {
     fn from(algo: Algorithm) -> Self {
         match algo {
             Algorithm::Sha256 => HashAlgorithm::Sha256,
+            Algorithm::Sha384 => HashAlgorithm::Sha384,
+            Algorithm::Sha512 => HashAlgorithm::Sha512,
             Algorithm::Md5 => HashAlgorithm::Md5,
             Algorithm::Crc32 => HashAlgorithm::Crc32,
             Algorithm::Blake2 => HashAlgorithm::Blake2,
-            Algorithm::Xxh3 => HashAlgorithm::Xxh3,
+            Algorithm::Blake3 => HashAlgorithm::Blake3,
+            Algorithm::Xxh3 | Algorithm::Auto => HashAlgorithm::Xxh3,
+            Algorithm::Xxh64 => HashAlgorithm::Xxh64,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BudgetOrder {
+    Smallest,
+    Largest,
+    Path,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DuplicatesFormat {
+    Text,
+    Json,
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Verify equality of two root directories", long_about = None)]
 struct Args {
@@ -41,317 +102,2761 @@ struct Args {
     #[arg(default_value = ".")]
     directory: PathBuf,
 
-    /// Checksum file to verify against
+    /// Checksum file to verify against; an http(s):// URL is downloaded
+    /// (requires the `network` feature) and `-` reads it from stdin
     #[arg(short, long)]
     check: Option<PathBuf>,
 
-    /// Hash algorithm to use
-    #[arg(short, long, value_enum, default_value = "sha256")]
+    /// Hash algorithm to use; a comma-separated list such as sha256,blake3
+    /// records several, as with --algorithms
+    #[arg(
+        short = 'a',
+        long = "algorithm",
+        id = "algorithm",
+        value_enum,
+        value_delimiter = ',',
+        value_name = "ALGORITHM",
+        default_value = "sha256"
+    )]
+    algorithm_list: Vec<Algorithm>,
+
+    /// The first algorithm of -a or --algorithms, set once the arguments
+    /// are parsed
+    #[arg(skip = Algorithm::Sha256)]
     algorithm: Algorithm,
 
+    /// Hash with several algorithms in one read of each file, e.g.
+    /// sha256,md5; the first gives each entry's `hash`, the others are stored
+    /// in its `hashes` (JSON and JSONL only)
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "ALGOS",
+        conflicts_with = "algorithm"
+    )]
+    algorithms: Vec<Algorithm>,
+
     /// Output file for checksums (default: stdout)
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Checksum file format; when verifying, names like SHA256SUMS or
+    /// *.sha256 are read as coreutils files, *.jsonl as JSONL and *.csv as
+    /// CSV unless another format is given
+    #[arg(long, value_enum, default_value = "json")]
+    format: ManifestFormat,
+
+    /// Format of the checksum file read by -c, overriding --format; by
+    /// default it is detected from the file's name and first line
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    input_format: Option<ManifestFormat>,
+
+    /// End each coreutils record with a NUL byte instead of a newline, like
+    /// `sha256sum -z`, so paths holding newlines survive pipes and xargs -0;
+    /// with -c, reads a checksum file written that way
+    #[arg(short = '0', long)]
+    null: bool,
+
+    /// Mix each file's relative path into its hash so renames are detected
+    #[arg(long)]
+    hash_includes_name: bool,
+
+    /// How hashes are written when generating (base64 needs --format json);
+    /// verification reads the encoding from the checksum file
+    #[arg(long, value_enum, default_value = "hex")]
+    encoding: HashEncoding,
+
+    /// Keep only the first N characters of each file hash; shorter
+    /// hashes are easier to compare by eye but far weaker against collisions
+    #[arg(long, value_name = "N", value_parser = parse_nonzero)]
+    short: Option<usize>,
+
+    /// Secret for HMAC-SHA256/384/512 hashes, given directly or read from
+    /// @FILE; only a check value is stored, and verifying needs the same key
+    #[arg(long, value_name = "KEY|@FILE", value_parser = parse_key)]
+    hmac_key: Option<Arc<[u8]>>,
+
+    /// Trust files matching this glob (relative path) without hashing them;
+    /// they are recorded with a placeholder and only checked for existence
+    #[arg(long, value_name = "GLOB")]
+    assume_unchanged: Vec<String>,
+
+    /// Hash files matching this glob (relative path) with every carriage
+    /// return removed, so CRLF and LF copies match; such hashes differ from
+    /// sha256sum and other tools that hash the bytes as stored
+    #[arg(long, value_name = "GLOB")]
+    text_normalize: Vec<String>,
+
+    /// Skip files smaller than this, e.g. 4K; K, M, G and T are powers of
+    /// 1024 (also written KiB, MiB, ...), KB, MB, GB and TB powers of 1000
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than this, e.g. 100M or 2G; units as for --min-size
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only hash files until their cumulative size would exceed this many bytes
+    #[arg(long, value_name = "BYTES")]
+    size_budget: Option<u64>,
+
+    /// Order in which files are admitted under --size-budget
+    #[arg(long, value_enum, default_value = "smallest")]
+    budget_order: BudgetOrder,
+
+    /// Generate mode: print groups of files with identical content (text or
+    /// json); without --output the checksum file itself is not printed
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "text"
+    )]
+    find_duplicates: Option<DuplicatesFormat>,
+
+    /// Write the resolved list of files (after filtering) to this plan file
+    #[arg(long, value_name = "PATH")]
+    write_plan: Option<PathBuf>,
+
+    /// Use the files listed in this plan instead of walking the directory
+    #[arg(long, value_name = "PATH")]
+    plan: Option<PathBuf>,
+
+    /// Hash exactly the files listed in FILE (one per line, relative to
+    /// DIRECTORY or absolute; `-` reads stdin) instead of walking DIRECTORY
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Generate mode: reuse hashes from this earlier checksum file for files
+    /// whose size and modification time haven't changed
+    #[arg(long, value_name = "EXISTING")]
+    update: Option<PathBuf>,
+
+    /// Generate mode: save hashed entries to this JSONL file as the run goes.
+    /// Rerunning with the same checkpoint after an interruption skips files
+    /// it holds whose size and modification time still match; it is deleted
+    /// once the output is written without errors.
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Verify mode: only check entries whose path equals or matches this
+    /// relative path or glob
+    #[arg(long, value_name = "PATH")]
+    only: Option<String>,
+
+    /// Verify mode: only check entries under this relative directory, e.g.
+    /// after restoring just that part of the tree; the rest are counted as
+    /// not selected rather than missing
+    #[arg(long, value_name = "REL")]
+    subpath: Option<String>,
+
+    /// Exit non-zero when files listed in the checksum file are missing (default)
+    #[arg(
+        long,
+        alias = "fail-on-extra-in-manifest",
+        overrides_with = "allow_missing"
+    )]
+    fail_on_missing: bool,
+
+    /// Report files missing from the target without failing the run
+    #[arg(long, overrides_with = "fail_on_missing")]
+    allow_missing: bool,
+
+    /// Verify mode: stop checking further files after the first failure
+    /// (files already being hashed still finish)
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Verify mode: when a recorded path is not found, accept a file whose
+    /// name differs only in case, with a warning
+    #[arg(long)]
+    ignore_case: bool,
+
+    /// Verify mode: before checking any file, recompute the checksum file's
+    /// manifest_hash and fail if it doesn't match the recorded one
+    #[arg(long)]
+    verify_manifest: bool,
+
+    /// Verify mode: also fail on files under the root that the checksum file doesn't list
+    #[arg(long)]
+    detect_added: bool,
+
+    /// Verify mode: also fail when any entry was skipped (e.g. by --skip-newer)
+    #[arg(long)]
+    strict: bool,
+
+    /// Verify mode: write every entry's status and the summary counts to
+    /// this JSON file; with --diff, the paths that differ and their counts
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Verify mode: append the relative path of each failed entry to this
+    /// file, one per line, usable as a --plan for re-verification
+    #[arg(long, value_name = "FILE")]
+    failures_out: Option<PathBuf>,
+
+    /// Stop printing individual failures after this many (0 = unlimited)
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    max_printed_failures: usize,
+
+    /// Verify mode: compare recorded sizes instead of hashing; entries
+    /// without a recorded size are skipped
+    #[arg(long)]
+    size_only: bool,
+
+    /// Don't hash anything: in verify mode only check that files exist (and
+    /// sizes match, if recorded); when generating, list the files that would
+    /// be hashed and their total size
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Store paths relative to the output file's directory instead of DIRECTORY
+    /// (requires --output)
+    #[arg(long)]
+    manifest_dir_relative: bool,
+
+    /// Store each file's canonical absolute path instead of a path relative
+    /// to DIRECTORY; verifying such a file ignores DIRECTORY and rejects --root
+    #[arg(long)]
+    absolute_paths: bool,
+
+    /// Also write FILE.<algorithm> next to each hashed file, holding its
+    /// checksum line for `sha256sum -c` and friends; existing sidecars are
+    /// not hashed. Without --output, no manifest is printed
+    #[arg(long, visible_alias = "output-dir-sidecar")]
+    sidecar: bool,
+
+    /// Write the output file in place instead of via a temporary file and rename
+    #[arg(long)]
+    no_atomic: bool,
+
     /// Exclude patterns (can be specified multiple times)
     #[arg(short, long)]
     exclude: Vec<String>,
 
-    /// Only check files older than those on target (requires -c)
+    /// Fail on glob patterns that don't parse instead of warning and
+    /// ignoring them
+    #[arg(long)]
+    strict_patterns: bool,
+
+    /// Read exclude patterns from this file, one per line; blank lines and
+    /// lines starting with '#' are ignored (can be specified multiple times)
+    #[arg(long, value_name = "FILE")]
+    exclude_from: Vec<PathBuf>,
+
+    /// Only process files matching one of these patterns (can be specified
+    /// multiple times); excludes still take precedence
+    #[arg(short, long)]
+    include: Vec<String>,
+
+    /// Only process files with this extension, e.g. `iso` or `.tar.gz`
+    /// (case-insensitive; can be specified multiple times); combines with
+    /// --include and --exclude
+    #[arg(long, value_name = "EXTENSION", value_parser = parse_extension)]
+    ext: Vec<String>,
+
+    /// Skip files ignored by .gitignore, .ignore and global git excludes
+    #[arg(long)]
+    gitignore: bool,
+
+    /// Skip hidden files and directories, whose name starts with `.`
+    #[arg(long)]
+    ignore_hidden: bool,
+
+    /// Hash what symlinks point to instead of recording their targets;
+    /// symlink cycles are skipped
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Record empty directories too; verification checks they still exist
+    /// and are still empty
+    #[arg(long)]
+    track_dirs: bool,
+
+    /// Read FIFOs, sockets and devices like regular files instead of
+    /// skipping them; reading one can block or never end
+    #[arg(long)]
+    allow_special: bool,
+
+    /// Only descend N directory levels; 1 hashes just the files directly in
+    /// DIRECTORY, 2 also those one directory down
+    #[arg(long, value_name = "N", value_parser = parse_nonzero)]
+    max_depth: Option<usize>,
+
+    /// Only check files older than those on target (requires -c); when
+    /// generating, record the modification times this needs
     #[arg(long)]
     skip_newer: bool,
 
+    /// Only verify files modified at or after TIME (RFC 3339, e.g.
+    /// 2024-05-01T00:00:00Z, or seconds since the Unix epoch), going by the
+    /// recorded modification time or, without one, the file's current one
+    #[arg(long, value_name = "TIME", value_parser = parse_timestamp)]
+    changed_since: Option<u64>,
+
     /// Root directory for verification (when using -c)
     #[arg(short, long)]
     root: Option<PathBuf>,
 
-    /// Number of threads to use
-    #[arg(short, long, default_value = "0")]
+    /// Number of hashing threads, or `auto` (same as 0) for one per core
+    #[arg(
+        short,
+        long,
+        visible_alias = "hash-threads",
+        default_value = "auto",
+        allow_negative_numbers = true,
+        value_parser = parse_threads
+    )]
     threads: usize,
 
+    /// Read files on this many separate threads and hand the data to the
+    /// hashing threads, so slow reads don't stall hashing (0 = off); each
+    /// file in flight then buffers a few chunks of --buffer-size
+    #[arg(long, value_name = "N", default_value = "0")]
+    read_threads: usize,
+
+    /// Concurrent range reads to issue ahead of the hasher for large files (0 = sequential)
+    #[arg(long, default_value = "0")]
+    prefetch: usize,
+
+    /// Bytes to read at a time while hashing; each thread holds one buffer
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = DEFAULT_BUFFER_SIZE,
+        value_parser = parse_nonzero
+    )]
+    buffer_size: usize,
+
+    /// Give up on a file that takes longer than this many seconds to hash,
+    /// e.g. on a stalled network mount, and report it as an error
+    #[arg(long, value_name = "SECONDS", value_parser = parse_nonzero)]
+    file_timeout: Option<usize>,
+
+    /// Retry a file up to N times, waiting longer each time, when reading it
+    /// fails with an I/O error other than not found or permission denied
+    #[arg(long, value_name = "N", default_value = "0")]
+    retries: u32,
+
+    /// Memory-map files of 16 MiB or more instead of reading them; a file
+    /// truncated by another process while mapped crashes the run
+    #[arg(long)]
+    mmap: bool,
+
     /// Verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Only print warnings, errors, failures and, when something failed,
+    /// the summary; overrides --verbose
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Record hashes of NTFS alternate data streams (Windows only)
+    #[arg(long)]
+    record_ads: bool,
+
+    /// Compare recorded NTFS alternate data streams during verification (Windows only)
+    #[arg(long)]
+    verify_ads: bool,
+
+    /// Record file capabilities (security.capability xattr) (Linux only)
+    #[arg(long)]
+    record_caps: bool,
+
+    /// Compare recorded file capabilities during verification (Linux only)
+    #[arg(long)]
+    verify_caps: bool,
+
+    /// Record Unix permission bits; verification checks them whenever they
+    /// were recorded (Unix only)
+    #[arg(long)]
+    permissions: bool,
+
+    /// How to report progress: `json` writes one event object per line to
+    /// stdout (phase, processed, total, bytes) and a final summary object
+    #[arg(long, value_enum, value_name = "FORMAT", default_value = "bar")]
+    progress: ProgressFormat,
+
+    /// Emit only an integer completion percentage (0-100) whenever it changes
+    #[arg(long)]
+    progress_percent: bool,
+
+    /// Stream that receives --progress-percent updates
+    #[arg(long, value_enum, default_value = "stderr")]
+    progress_stream: ProgressStream,
+
+    /// Color the OK, FAILED and SKIPPED labels of verify output; auto colors
+    /// them only when stderr is a terminal and NO_COLOR is unset
+    #[arg(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    color: ColorChoice,
+
+    /// Print a summary of a checksum file without touching the filesystem
+    #[arg(long, value_name = "MANIFEST")]
+    info: Option<PathBuf>,
+
+    /// Classify changes in A and B against a common BASE manifest
+    /// (A and B may each be a manifest or a directory)
+    #[arg(long, num_args = 3, value_names = ["BASE", "A", "B"])]
+    three_way: Option<Vec<PathBuf>>,
+
+    /// Compare two checksum files by path without reading any files, listing
+    /// paths added, removed and changed from A to B
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    diff: Option<Vec<PathBuf>>,
+
+    /// Hash data piped to stdin and print only the resulting hash
+    #[arg(long)]
+    stdin: bool,
+
+    /// Hash known inputs with every algorithm and compare the digests with
+    /// published test vectors, to check a build or an upgraded dependency
+    #[arg(long, hide = true, alias = "verify-self")]
+    self_test: bool,
+
+    /// Compare DIRECTORY (A) with another directory (B) without a checksum file
+    #[arg(long, value_name = "OTHER_DIR")]
+    compare: Option<PathBuf>,
+
+    /// With --compare, write the differing paths with both hashes, the
+    /// paths found on one side only and the counts to this JSON file
+    #[arg(long, value_name = "FILE", requires = "compare")]
+    compare_report: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ChecksumEntry {
-    path: String,
-    hash: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    modified: Option<u64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    size: Option<u64>,
+/// Why a run failed; each variant maps to a documented exit code.
+#[derive(Debug)]
+enum RunError {
+    /// Verification or comparison found differences (exit code 1)
+    Mismatch,
+    /// Reading or writing failed, or the arguments don't make sense
+    /// together (exit code 2)
+    Io(Box<dyn Error>),
+    /// A checksum file is malformed (exit code 3)
+    Parse(Box<dyn Error>),
+}
+
+impl<E: Into<Box<dyn Error>>> From<E> for RunError {
+    fn from(error: E) -> Self {
+        RunError::Io(error.into())
+    }
+}
+
+/// Classifies an error from loading a checksum file: failing to read it is
+/// an I/O error, anything else means its contents are malformed.
+fn load_error(error: Box<dyn Error>) -> RunError {
+    let is_io = error.is::<io::Error>()
+        || error
+            .downcast_ref::<serde_json::Error>()
+            .is_some_and(serde_json::Error::is_io);
+    if is_io {
+        RunError::Io(error)
+    } else {
+        RunError::Parse(error)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ChecksumFile {
-    version: String,
-    algorithm: String,
-    entries: Vec<ChecksumEntry>,
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(RunError::Mismatch) => ExitCode::from(1),
+        Err(RunError::Io(e)) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(2)
+        }
+        Err(RunError::Parse(e)) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(3)
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+fn run() -> Result<(), RunError> {
+    let mut args = Args::parse();
+    for path in args.exclude_from.clone() {
+        let patterns = read_patterns(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        args.exclude.extend(patterns);
+    }
+    check_patterns(&args)?;
+    // A single -a value keeps the single-algorithm mode
+    args.algorithm = args.algorithm_list[0];
+    if args.algorithm_list.len() > 1 {
+        args.algorithms = mem::take(&mut args.algorithm_list);
+    }
+    if let Some(&first) = args.algorithms.first() {
+        args.algorithm = first;
+    }
+    if args.quiet {
+        QUIET.store(true, Ordering::Relaxed);
+        args.verbose = false;
+    }
+    color::init(args.color);
+
+    if (args.record_ads || args.verify_ads) && !cfg!(windows) {
+        eprintln!("Warning: alternate data streams are only supported on Windows; ignoring");
+    }
+    if (args.record_caps || args.verify_caps) && !cfg!(target_os = "linux") {
+        eprintln!("Warning: file capabilities are only supported on Linux; ignoring");
+    }
+    if args.permissions && !cfg!(unix) {
+        eprintln!("Warning: permission bits are only supported on Unix; ignoring");
+    }
 
     // Set thread pool size
     if args.threads > 0 {
-        rayon::ThreadPoolBuilder::new()
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
             .num_threads(args.threads)
             .build_global()
-            .unwrap();
+        {
+            // Only I/O failures carry a source; the rest mean a pool is already set up
+            if e.source().is_some() {
+                return Err(format!("Cannot create thread pool: {}", e).into());
+            }
+            eprintln!(
+                "Warning: {} Continuing with {} threads",
+                e,
+                rayon::current_num_threads()
+            );
+        }
+    }
+    // --info and --diff only read checksum files, and --stdin and
+    // --self-test hash on one thread
+    if args.info.is_none() && args.diff.is_none() && !args.stdin && !args.self_test {
+        let threads = rayon::current_num_threads();
+        let noun = if threads == 1 { "thread" } else { "threads" };
+        let source = if args.threads == 0 { " (auto)" } else { "" };
+        info!("Using {} hashing {}{}", threads, noun, source);
+    }
+
+    if let Some(available) = available_memory() {
+        let buffers = args.buffer_size as u64 * rayon::current_num_threads() as u64;
+        if buffers > available / 2 {
+            eprintln!(
+                "Warning: --buffer-size {} across {} threads needs {} bytes, but only {} are available",
+                args.buffer_size,
+                rayon::current_num_threads(),
+                buffers,
+                available
+            );
+        }
+    }
+
+    if args.progress == ProgressFormat::Json && args.progress_percent {
+        return Err("--progress json cannot be combined with --progress-percent".into());
     }
 
-    if let Some(ref checksum_file) = args.check {
+    if args.self_test {
+        run_self_test()
+    } else if args.stdin {
+        if args.hmac_key.is_some() {
+            return Err("--hmac-key cannot be combined with --stdin".into());
+        }
+        let digest = hash_reader_raw(io::stdin().lock(), args.algorithm.into(), args.buffer_size)?;
+        println!("{}", args.encoding.encode(&digest));
+        Ok(())
+    } else if let Some(ref manifest) = args.info {
+        print_manifest_info(&args, manifest)
+    } else if let Some(ref paths) = args.diff {
+        diff_manifests(&args, paths)
+    } else if let Some(ref paths) = args.three_way {
+        three_way_compare(&args, paths)
+    } else if let Some(ref other) = args.compare {
+        compare_directories(&args, other)
+    } else if let Some(ref checksum_file) = args.check {
         verify_checksums(&args, checksum_file)
     } else {
         generate_checksums(&args)
     }
 }
 
-fn should_exclude(path: &Path, patterns: &[Pattern]) -> bool {
-    patterns.iter().any(|pattern| {
-        path.to_str()
-            .map(|s| pattern.matches(s))
-            .unwrap_or(false)
-    })
+/// Runs [`hashing::self_test`], failing if any hasher gave a wrong digest.
+fn run_self_test() -> Result<(), RunError> {
+    match hashing::self_test() {
+        Ok(checked) => {
+            info!(
+                "Self-test passed: {} digests match their test vectors",
+                checked
+            );
+            Ok(())
+        }
+        Err(failures) => {
+            for failure in &failures {
+                eprintln!("FAILED: {}", failure);
+            }
+            eprintln!("Self-test failed: {} digests are wrong", failures.len());
+            Err(RunError::Mismatch)
+        }
+    }
 }
 
-fn generate_checksums(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
-    let exclude_patterns: Vec<Pattern> = args
-        .exclude
-        .iter()
-        .filter_map(|p| Pattern::new(p).ok())
-        .collect();
-
-    let mut entries = Vec::new();
-    let processed = Arc::new(AtomicUsize::new(0));
-    let errors = Arc::new(AtomicUsize::new(0));
-
-    eprintln!("Scanning directory: {}", args.directory.display());
-
-    // Collect all files
-    let files: Vec<_> = WalkDir::new(&args.directory)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| !should_exclude(e.path(), &exclude_patterns))
-        .collect();
-
-    let total_files = files.len();
-    eprintln!("Found {} files to process", total_files);
-
-    // Process files in parallel
-    let results: Vec<_> = files
-        .par_iter()
-        .filter_map(|entry| {
-            let path = entry.path();
-            let relative_path = path
-                .strip_prefix(&args.directory)
-                .unwrap_or(path)
-                .to_string_lossy()
-                .to_string();
-
-            match process_file(path, &relative_path, args.algorithm.into(), args.skip_newer) {
-                Ok(checksum_entry) => {
-                    let count = processed.fetch_add(1, Ordering::Relaxed) + 1;
-                    if args.verbose || count % 100 == 0 {
-                        eprint!("\rProcessed: {}/{}", count, total_files);
-                    }
-                    Some(checksum_entry)
-                }
-                Err(e) => {
-                    errors.fetch_add(1, Ordering::Relaxed);
-                    eprintln!("\nError processing {}: {}", path.display(), e);
-                    None
+/// Warns about glob patterns that don't parse, since they would otherwise
+/// be dropped silently; with --strict-patterns they are an error instead.
+fn check_patterns(args: &Args) -> Result<(), RunError> {
+    let flags = [
+        ("--exclude", &args.exclude),
+        ("--include", &args.include),
+        ("--assume-unchanged", &args.assume_unchanged),
+        ("--text-normalize", &args.text_normalize),
+    ];
+    for (flag, patterns) in flags {
+        for pattern in patterns {
+            if let Err(e) = Pattern::new(pattern) {
+                let message = format!("invalid {} pattern '{}': {}", flag, pattern, e);
+                if args.strict_patterns {
+                    return Err(message.into());
                 }
+                eprintln!("Warning: {}; it is ignored", message);
             }
-        })
-        .collect();
+        }
+    }
+    Ok(())
+}
 
-    eprintln!("\rProcessed: {}/{}", total_files, total_files);
+fn walk_options(args: &Args) -> WalkOptions {
+    WalkOptions {
+        include: compile_patterns(&args.include),
+        exclude: compile_patterns(&args.exclude),
+        extensions: args.ext.clone(),
+        gitignore: args.gitignore,
+        ignore_hidden: args.ignore_hidden,
+        follow_symlinks: args.follow_symlinks,
+        track_dirs: args.track_dirs,
+        allow_special: args.allow_special,
+        max_depth: args.max_depth,
+    }
+}
 
-    entries.extend(results);
+fn generate_options(args: &Args) -> GenerateOptions {
+    GenerateOptions {
+        directory: args.directory.clone(),
+        walk: walk_options(args),
+        algorithm: args.algorithm.into(),
+        extra_algorithms: extra_algorithms(args),
+        record_modified: args.skip_newer || args.update.is_some() || args.checkpoint.is_some(),
+        record_ads: args.record_ads,
+        record_caps: args.record_caps,
+        record_mode: args.permissions,
+        hash_includes_name: args.hash_includes_name,
+        assume_unchanged: compile_patterns(&args.assume_unchanged),
+        text_normalize: compile_patterns(&args.text_normalize),
+        previous: HashMap::new(),
+        hash: hash_options(args),
+    }
+}
 
-    // Sort entries for consistent output
-    entries.sort_by(|a, b| a.path.cmp(&b.path));
+/// The --algorithms after the first, without repeats.
+fn extra_algorithms(args: &Args) -> Vec<HashAlgorithm> {
+    let primary = HashAlgorithm::from(args.algorithm);
+    let mut extras = Vec::new();
+    for &algorithm in args.algorithms.iter().skip(1) {
+        let algorithm = HashAlgorithm::from(algorithm);
+        if algorithm != primary && !extras.contains(&algorithm) {
+            extras.push(algorithm);
+        }
+    }
+    extras
+}
 
-    let checksum_file = ChecksumFile {
-        version: "1.0".to_string(),
-        algorithm: format!("{:?}", args.algorithm).to_lowercase(),
-        entries,
-    };
+/// Most hashing threads accepted; far more than any machine has cores, but
+/// low enough to catch a typo before thousands of threads are spawned.
+const MAX_THREADS: usize = 1024;
 
-    // Write output
-    let output_json = serde_json::to_string_pretty(&checksum_file)?;
-    
-    if let Some(output_path) = &args.output {
-        fs::write(output_path, output_json)?;
-        eprintln!("Checksums written to: {}", output_path.display());
-    } else {
-        println!("{}", output_json);
+/// Parses --threads: `auto` or 0 for one per core, else a thread count.
+fn parse_threads(value: &str) -> Result<usize, String> {
+    if value == "auto" {
+        return Ok(0);
     }
-
-    let error_count = errors.load(Ordering::Relaxed);
-    if error_count > 0 {
-        eprintln!("Warning: {} errors occurred during processing", error_count);
+    match value.parse::<usize>() {
+        Ok(threads) if threads > MAX_THREADS => Err(format!(
+            "{} threads is more than the limit of {}",
+            threads, MAX_THREADS
+        )),
+        Ok(threads) => Ok(threads),
+        Err(_) if value.parse::<i64>().is_ok() => Err("cannot be negative".to_string()),
+        Err(_) => Err("must be a number of threads or 'auto'".to_string()),
     }
+}
 
-    Ok(())
+/// Parses --ext: lowercased, without the leading dot.
+fn parse_extension(value: &str) -> Result<String, String> {
+    let extension = value.strip_prefix('.').unwrap_or(value);
+    if extension.is_empty() || extension.contains(['/', '\\']) {
+        return Err(format!("'{}' is not a file extension", value));
+    }
+    Ok(extension.to_lowercase())
 }
 
-fn process_file(
-    path: &Path,
-    relative_path: &str,
-    algorithm: HashAlgorithm,
-    include_metadata: bool,
-) -> Result<ChecksumEntry, Box<dyn std::error::Error>> {
-    let hash = hash_file(path, algorithm)?;
-    
-    let (modified, size) = if include_metadata {
-        let metadata = fs::metadata(path)?;
-        let modified = metadata
-            .modified()?
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_secs();
-        (Some(modified), Some(metadata.len()))
-    } else {
-        (None, None)
+/// Parses a size such as `500`, `100M` or `2GiB`. K, M, G and T, alone or
+/// followed by `iB`, are powers of 1024; KB, MB, GB and TB are powers of 1000.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a size such as 500, 100M or 2G", value))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KIB" => 1 << 10,
+        "M" | "MIB" => 1 << 20,
+        "G" | "GIB" => 1 << 30,
+        "T" | "TIB" => 1 << 40,
+        "KB" => 1_000,
+        "MB" => 1_000_000,
+        "GB" => 1_000_000_000,
+        "TB" => 1_000_000_000_000,
+        _ => return Err(format!("unknown size unit '{}'", unit.trim())),
     };
-
-    Ok(ChecksumEntry {
-        path: relative_path.to_string(),
-        hash,
-        modified,
-        size,
-    })
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("'{}' is too large", value))
 }
 
-fn verify_checksums(
-    args: &Args,
-    checksum_path: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file = File::open(checksum_path)?;
-    let reader = BufReader::new(file);
-    let checksum_file: ChecksumFile = serde_json::from_reader(reader)?;
-
-    eprintln!("Verifying {} files using {} algorithm", 
-              checksum_file.entries.len(), 
-              checksum_file.algorithm);
+fn parse_nonzero(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be greater than zero".to_string()),
+        Ok(size) => Ok(size),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    let root_dir = args.root.as_ref().unwrap_or(&args.directory);
-    let processed = Arc::new(AtomicUsize::new(0));
-    let failed = Arc::new(AtomicUsize::new(0));
-    let skipped = Arc::new(AtomicUsize::new(0));
-    let total = checksum_file.entries.len();
+/// Parses seconds since the Unix epoch or an RFC 3339 date-time such as
+/// `2024-05-01T12:00:00Z` or `2024-05-01T14:00:00.5+02:00`.
+fn parse_timestamp(value: &str) -> Result<u64, String> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(seconds);
+    }
+    let invalid = || {
+        format!(
+            "expected seconds since the epoch or an RFC 3339 time, not '{}'",
+            value
+        )
+    };
+    let field = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        value
+            .get(range)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let bytes = value.as_bytes();
+    if bytes.len() < 20
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+        || bytes[13] != b':'
+        || bytes[16] != b':'
+    {
+        return Err(invalid());
+    }
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
+    }
 
-    // Parse algorithm from checksum file
-    let algorithm = match checksum_file.algorithm.as_str() {
-        "sha256" => HashAlgorithm::Sha256,
-        "md5" => HashAlgorithm::Md5,
-        "crc32" => HashAlgorithm::Crc32,
-        "blake2" => HashAlgorithm::Blake2,
-        "xxh3" => HashAlgorithm::Xxh3,
-        _ => {
-            eprintln!("Warning: Unknown algorithm '{}', using SHA256", checksum_file.algorithm);
-            HashAlgorithm::Sha256
+    // Fractional seconds are dropped; recorded times are whole seconds
+    let mut rest = &value[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return Err(invalid());
         }
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && rest.as_bytes()[3] == b':' => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(invalid()),
+            };
+            let start = value.len() - 5;
+            sign * (field(start..start + 2)? * 3600 + field(start + 3..start + 5)? * 60)
+        }
+        _ => return Err(invalid()),
     };
 
-    // Verify files in parallel
-    let _results: Vec<_> = checksum_file
-        .entries
-        .par_iter()
-        .map(|entry| {
-            let full_path = root_dir.join(&entry.path);
-            let result = verify_single_file(&full_path, entry, algorithm, args.skip_newer);
-            
-            match &result {
-                VerifyResult::Ok => {
-                    let count = processed.fetch_add(1, Ordering::Relaxed) + 1;
-                    if args.verbose {
-                        eprintln!("OK: {}", entry.path);
-                    } else if count % 100 == 0 {
-                        eprint!("\rVerified: {}/{}", count, total);
-                    }
-                }
-                VerifyResult::Failed(msg) => {
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    eprintln!("\nFAILED: {} - {}", entry.path, msg);
-                }
-                VerifyResult::Skipped(msg) => {
-                    skipped.fetch_add(1, Ordering::Relaxed);
-                    if args.verbose {
-                        eprintln!("SKIPPED: {} - {}", entry.path, msg);
-                    }
-                }
-            }
-            
-            (entry.path.clone(), result)
-        })
-        .collect();
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era_year = y.rem_euclid(400);
+    let era_day = era_year * 365 + era_year / 4 - era_year / 100 + (153 * m + 2) / 5 + day - 1;
+    let days = y.div_euclid(400) * 146_097 + era_day - 719_468;
 
-    eprintln!("\rVerified: {}/{}", total, total);
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    u64::try_from(seconds).map_err(|_| format!("'{}' is before the Unix epoch", value))
+}
 
-    // Summary
-    let ok_count = processed.load(Ordering::Relaxed);
-    let fail_count = failed.load(Ordering::Relaxed);
-    let skip_count = skipped.load(Ordering::Relaxed);
+/// Reads an --hmac-key value: the key itself, or `@path` for a key file,
+/// whose trailing newline is dropped.
+fn parse_key(value: &str) -> Result<Arc<[u8]>, String> {
+    let mut key = match value.strip_prefix('@') {
+        Some(path) => fs::read(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => value.as_bytes().to_vec(),
+    };
+    if key.ends_with(b"\n") {
+        key.pop();
+        if key.ends_with(b"\r") {
+            key.pop();
+        }
+    }
+    if key.is_empty() {
+        return Err("the key must not be empty".to_string());
+    }
+    Ok(key.into())
+}
 
-    eprintln!("\nSummary:");
-    eprintln!("  OK:      {}", ok_count);
-    eprintln!("  Failed:  {}", fail_count);
-    eprintln!("  Skipped: {}", skip_count);
-    eprintln!("  Total:   {}", total);
+/// Memory the kernel reports as available for new allocations, if known.
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
 
-    if fail_count > 0 {
-        std::process::exit(1);
+fn hash_options(args: &Args) -> HashOptions {
+    HashOptions {
+        prefetch: args.prefetch,
+        buffer_size: args.buffer_size,
+        mmap: args.mmap,
+        read_threads: args.read_threads,
+        timeout: args
+            .file_timeout
+            .map(|secs| Duration::from_secs(secs as u64)),
+        key: args.hmac_key.clone(),
+        encoding: args.encoding,
+        retries: args.retries,
+        ..HashOptions::default()
     }
+}
 
-    Ok(())
+/// Reads a plan file: one relative path per line, blank lines ignored.
+fn read_plan(plan_path: &Path) -> std::io::Result<Vec<String>> {
+    Ok(fs::read_to_string(plan_path)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
 }
 
-enum VerifyResult {
-    Ok,
-    Failed(String),
-    Skipped(String),
+/// Reads a --files-from list, one path per line, from stdin for `-`.
+fn read_file_list(path: &Path) -> io::Result<Vec<String>> {
+    let text = if path == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path)?
+    };
+    Ok(text
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
 }
 
-fn verify_single_file(
-    path: &Path,
-    entry: &ChecksumEntry,
-    algorithm: HashAlgorithm,
-    skip_newer: bool,
-) -> VerifyResult {
-    if !path.exists() {
-        return VerifyResult::Failed("File not found".to_string());
-    }
-
-    // Check if we should skip newer files
-    if skip_newer && entry.modified.is_some() {
-        match fs::metadata(path) {
-            Ok(metadata) => {
-                if let Ok(modified) = metadata.modified() {
-                    if let Ok(duration) = modified.duration_since(SystemTime::UNIX_EPOCH) {
-                        let current_modified = duration.as_secs();
-                        if current_modified > entry.modified.unwrap() {
-                            return VerifyResult::Skipped("File is newer on target".to_string());
-                        }
-                    }
-                }
-            }
-            Err(e) => return VerifyResult::Failed(format!("Cannot read metadata: {}", e)),
+/// Reads glob patterns, one per line, skipping blank lines and `#` comments.
+fn read_patterns(path: &Path) -> std::io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Why `path` is left out by --min-size or --max-size, if it is. Only
+/// regular files are filtered; links and directories have no content size.
+fn outside_size_limits(path: &Path, args: &Args) -> Option<String> {
+    if args.min_size.is_none() && args.max_size.is_none() {
+        return None;
+    }
+    let metadata = if args.follow_symlinks {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    }
+    .ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    match (args.min_size, args.max_size) {
+        (Some(min), _) if metadata.len() < min => {
+            Some(format!("{} bytes is below --min-size", metadata.len()))
         }
+        (_, Some(max)) if metadata.len() > max => {
+            Some(format!("{} bytes is above --max-size", metadata.len()))
+        }
+        _ => None,
     }
+}
 
-    match hash_file(path, algorithm) {
-        Ok(hash) => {
-            if hash == entry.hash {
-                VerifyResult::Ok
-            } else {
-                VerifyResult::Failed(format!("Hash mismatch: expected {}, got {}", entry.hash, hash))
-            }
+fn write_plan(plan_path: &Path, relative_paths: &[String]) -> std::io::Result<()> {
+    let mut contents = String::new();
+    for path in relative_paths {
+        contents.push_str(path);
+        contents.push('\n');
+    }
+    fs::write(plan_path, contents)
+}
+
+fn generate_checksums(args: &Args) -> Result<(), RunError> {
+    if args.hash_includes_name
+        && matches!(
+            args.format,
+            ManifestFormat::Coreutils
+                | ManifestFormat::Bsd
+                | ManifestFormat::Sri
+                | ManifestFormat::Csv
+        )
+    {
+        return Err(
+            "--hash-includes-name cannot be recorded in coreutils, BSD, SRI or CSV format".into(),
+        );
+    }
+    if args.null && args.format != ManifestFormat::Coreutils {
+        return Err("--null needs --format coreutils".into());
+    }
+    let algorithm = algorithm_name(args.algorithm.into());
+    if args.format == ManifestFormat::Sri && !formats::SRI_ALGORITHMS.contains(&algorithm.as_str())
+    {
+        return Err(format!(
+            "--format sri needs one of {}, not {}",
+            formats::SRI_ALGORITHMS.join(", "),
+            algorithm
+        )
+        .into());
+    }
+    let extras = extra_algorithms(args);
+    if !extras.is_empty() && !matches!(args.format, ManifestFormat::Json | ManifestFormat::Jsonl) {
+        return Err("--algorithms can only be recorded in JSON or JSONL format".into());
+    }
+    if !args.text_normalize.is_empty()
+        && !matches!(args.format, ManifestFormat::Json | ManifestFormat::Jsonl)
+    {
+        return Err("--text-normalize can only be recorded in JSON or JSONL format".into());
+    }
+    if args.hmac_key.is_some() {
+        let mut all = std::iter::once(HashAlgorithm::from(args.algorithm)).chain(extras);
+        if let Some(unsupported) = all.find(|algorithm| !algorithm.supports_hmac()) {
+            return Err(format!(
+                "--hmac-key needs sha256, sha384 or sha512, not {}",
+                algorithm_name(unsupported)
+            )
+            .into());
+        }
+        if args.format != ManifestFormat::Json {
+            return Err("--hmac-key can only be recorded in JSON format".into());
+        }
+    }
+    if args.encoding != HashEncoding::Hex && args.format != ManifestFormat::Json {
+        return Err("--encoding base64 can only be recorded in JSON format".into());
+    }
+    // The checkpoint header can't say which key or encoding its hashes used
+    if args.checkpoint.is_some() && (args.hmac_key.is_some() || args.encoding != HashEncoding::Hex)
+    {
+        return Err("--checkpoint cannot be combined with --hmac-key or --encoding base64".into());
+    }
+    if let (Some(min), Some(max)) = (args.min_size, args.max_size) {
+        if min > max {
+            return Err("--min-size cannot be larger than --max-size".into());
+        }
+    }
+    if args.sidecar {
+        let conflicts = [
+            ("--hash-includes-name", args.hash_includes_name),
+            ("--hmac-key", args.hmac_key.is_some()),
+            ("--encoding base64", args.encoding != HashEncoding::Hex),
+            ("--short", args.short.is_some()),
+            ("--manifest-dir-relative", args.manifest_dir_relative),
+            ("--format jsonl", args.format == ManifestFormat::Jsonl),
+            ("--text-normalize", !args.text_normalize.is_empty()),
+        ];
+        if let Some((flag, _)) = conflicts.iter().find(|(_, set)| *set) {
+            return Err(format!("--sidecar cannot be combined with {}", flag).into());
+        }
+    }
+    if let Some(length) = args.short {
+        // Verification only compares prefixes when the length is recorded
+        if !matches!(args.format, ManifestFormat::Json | ManifestFormat::Jsonl) {
+            return Err("--short can only be recorded in JSON or JSONL format".into());
+        }
+        if args.hmac_key.is_some() {
+            return Err("--short cannot be combined with --hmac-key".into());
+        }
+        eprintln!(
+            "Warning: --short {} keeps {} bits of each hash, which weakens collision resistance",
+            length,
+            length * args.encoding.bits_per_char()
+        );
+    }
+    if args.progress == ProgressFormat::Json && args.output.is_none() {
+        return Err("--progress json writes to stdout, so the checksums need --output".into());
+    }
+    if args.find_duplicates.is_some() {
+        if args.hash_includes_name {
+            return Err("--find-duplicates cannot compare hashes that include file names".into());
+        }
+        if args.format == ManifestFormat::Jsonl {
+            return Err("--find-duplicates cannot be combined with --format jsonl".into());
+        }
+    }
+    let mut options = generate_options(args);
+    if let Some(existing) = &args.update {
+        options.previous = load_previous(existing, &options, args.short)?;
+    }
+
+    let errors = AtomicUsize::new(0);
+    let mut timer = PhaseTimer::start();
+
+    // Unless something needs the whole list up front, files are hashed while
+    // the walk is still finding more
+    let streaming = args.plan.is_none()
+        && args.files_from.is_none()
+        && args.size_budget.is_none()
+        && args.write_plan.is_none()
+        && !args.dry_run
+        && !args.progress_percent;
+
+    // Collect all files
+    let mut files: Vec<PathBuf> = match (&args.plan, &args.files_from) {
+        (Some(_), Some(_)) => return Err("--plan and --files-from cannot be combined".into()),
+        (Some(plan_path), None) => {
+            info!("Using file list from plan: {}", plan_path.display());
+            read_plan(plan_path)?
+                .iter()
+                .map(|path| entry_path(&args.directory, path))
+                .collect()
+        }
+        (None, Some(list_path)) => {
+            info!("Using file list: {}", list_path.display());
+            read_file_list(list_path)
+                .map_err(|e| format!("{}: {}", list_path.display(), e))?
+                .iter()
+                .map(|path| entry_path(&args.directory, path))
+                .collect()
+        }
+        (None, None) => {
+            info!("Scanning directory: {}", args.directory.display());
+            if streaming {
+                Vec::new()
+            } else {
+                collect_files(&args.directory, &options.walk)
+            }
+        }
+    };
+
+    // Plans and file lists bypass the walk, which leaves special files out
+    if (args.plan.is_some() || args.files_from.is_some()) && !args.allow_special {
+        files.retain(|path| {
+            let special = is_special(path, args.follow_symlinks);
+            if special {
+                eprintln!("Skipped {}: not a regular file", path.display());
+            }
+            !special
+        });
+    }
+
+    // Never list the output manifest or the checkpoint in their own entries,
+    // nor the temporary files they are written to first
+    let canonical_checkpoint = args
+        .checkpoint
+        .as_deref()
+        .and_then(|checkpoint| canonical_output_path(checkpoint).ok())
+        .and_then(|checkpoint| Some((fs::canonicalize(&args.directory).ok()?, checkpoint)));
+    let canonical_paths = match &args.output {
+        Some(output) => canonical_output_path(output)
+            .and_then(|manifest| Ok((fs::canonicalize(&args.directory)?, manifest)))
+            .ok(),
+        None => None,
+    };
+    let is_output = |path: &Path| {
+        [&canonical_paths, &canonical_checkpoint]
+            .into_iter()
+            .flatten()
+            .any(|(directory, output)| {
+                let path = absolute_in(path, &args.directory, directory);
+                path == *output || path == temp_path(output)
+            })
+    };
+    files.retain(|path| !is_output(path));
+
+    // Sidecars from an earlier run are checksums, not content. Only those
+    // next to the file they are named after are skipped; any other
+    // `*.<algorithm>` file is hashed like the rest
+    let sidecar_extension = format!(".{}", algorithm);
+    let sidecars_skipped = AtomicUsize::new(0);
+    let not_sidecar = |path: &Path| {
+        let sibling = path
+            .to_str()
+            .filter(|_| args.sidecar)
+            .and_then(|path| path.strip_suffix(&sidecar_extension))
+            .map(Path::new)
+            .filter(|sibling| sibling.is_file());
+        match sibling {
+            Some(sibling) => {
+                sidecars_skipped.fetch_add(1, Ordering::Relaxed);
+                if args.verbose {
+                    eprintln!(
+                        "Skipped {}: sidecar of {}",
+                        path.display(),
+                        sibling.display()
+                    );
+                }
+                false
+            }
+            None => true,
+        }
+    };
+    files.retain(|path| not_sidecar(path));
+
+    // Size limits apply before hashing, so skipped files are never read
+    let size_skipped = AtomicUsize::new(0);
+    let within_size_limits = |path: &Path| match outside_size_limits(path, args) {
+        Some(reason) => {
+            size_skipped.fetch_add(1, Ordering::Relaxed);
+            if args.verbose {
+                eprintln!("Skipped {}: {}", path.display(), reason);
+            }
+            false
+        }
+        None => true,
+    };
+    files.retain(|path| within_size_limits(path));
+
+    let files = match args.size_budget {
+        Some(budget) => apply_size_budget(files, budget, args.budget_order),
+        None => files,
+    };
+
+    // With --manifest-dir-relative, stored paths are relative to the manifest's directory
+    let manifest_base = if args.manifest_dir_relative {
+        let (directory, manifest) = canonical_paths
+            .clone()
+            .ok_or("--manifest-dir-relative requires a resolvable --output")?;
+        let manifest_dir = manifest.parent().unwrap_or(&manifest).to_path_buf();
+        Some((directory, manifest_dir))
+    } else {
+        None
+    };
+    let absolute_root = if args.absolute_paths {
+        if args.manifest_dir_relative {
+            return Err("--absolute-paths cannot be combined with --manifest-dir-relative".into());
+        }
+        Some(fs::canonicalize(&args.directory)?)
+    } else {
+        None
+    };
+
+    if let Some(plan_path) = &args.write_plan {
+        let mut relative_paths: Vec<String> = files
+            .iter()
+            .map(|path| relative_path(path, &args.directory))
+            .collect();
+        relative_paths.sort();
+        write_plan(plan_path, &relative_paths)?;
+        info!("Plan written to: {}", plan_path.display());
+    }
+
+    let total_files = files.len();
+    if !streaming {
+        timer.lap("scan");
+        info!("Found {} files to process", total_files);
+    }
+
+    if args.dry_run {
+        return list_planned_files(args, &files, &options);
+    }
+
+    let checkpoint = match &args.checkpoint {
+        Some(path) => {
+            let checkpoint = Checkpoint::open(path, options.algorithm, options.hash_includes_name)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !checkpoint.recorded().is_empty() {
+                info!(
+                    "Resuming from checkpoint {} ({} entries)",
+                    path.display(),
+                    checkpoint.recorded().len()
+                );
+            }
+            let recorded = checkpoint.recorded().clone();
+            options.previous.extend(recorded);
+            Some(checkpoint)
+        }
+        None => None,
+    };
+
+    let completed = AtomicUsize::new(0);
+    let percent = args
+        .progress_percent
+        .then(|| PercentProgress::new(total_files, args.progress_stream));
+    let json_progress =
+        (args.progress == ProgressFormat::Json).then(|| JsonProgress::new("hash", total_files));
+
+    // Process files in parallel
+    let stored_path = |path: &Path| match (&manifest_base, &absolute_root) {
+        (Some((directory, manifest_dir)), _) => {
+            let absolute = absolute_in(path, &args.directory, directory);
+            portable_path(&path_relative_to(&absolute, manifest_dir))
+        }
+        (None, Some(directory)) => portable_path(&absolute_in(path, &args.directory, directory)),
+        (None, None) => relative_path(path, &args.directory),
+    };
+    let interval = if args.verbose { 1 } else { 100 };
+    let counter = if args.quiet || json_progress.is_some() {
+        FileCounter::quiet("Processed", total_files)
+    } else {
+        FileCounter::new("Processed", total_files, interval, percent.is_none())
+    };
+    // JSONL entries are written as they are hashed instead of being collected
+    let jsonl = match (args.format, &args.output) {
+        (ManifestFormat::Jsonl, Some(output_path)) => {
+            let output = OutputFile::create(output_path, !args.no_atomic)?;
+            let compression = Compression::from_path(output_path);
+            let encoder = Encoder::new(io::BufWriter::new(output), compression)?;
+            Some(JsonlSink::File(encoder))
+        }
+        (ManifestFormat::Jsonl, None) => Some(JsonlSink::Stdout(io::BufWriter::new(io::stdout()))),
+        _ => None,
+    }
+    .map(|sink| {
+        JsonlWriter::new(
+            sink,
+            options.algorithm,
+            options.hash_includes_name,
+            args.short,
+        )
+    })
+    .transpose()?;
+    let entries = Mutex::new(Vec::new());
+
+    let started = Instant::now();
+    let on_result = |path: &Path, result: Result<ChecksumEntry, String>| {
+        if let Some(percent) = &percent {
+            percent.update(completed.fetch_add(1, Ordering::Relaxed) + 1);
+        }
+        if let Some(progress) = &json_progress {
+            let bytes = result.as_ref().ok().and_then(|entry| entry.size);
+            progress.update(bytes.unwrap_or(0));
+        }
+
+        counter.inc();
+        if let (Some(checkpoint), Ok(entry)) = (&checkpoint, &result) {
+            checkpoint.record(entry);
+        }
+        match result.map(|entry| shorten(entry, args.short)) {
+            Ok(entry) => match &jsonl {
+                Some(writer) => writer.write_entry(&entry),
+                None => entries.lock().unwrap().push(entry),
+            },
+            Err(e) => {
+                errors.fetch_add(1, Ordering::Relaxed);
+                counter.println(&format!("\nError processing {}: {}", path.display(), e));
+            }
+        }
+    };
+    let stats = if streaming {
+        let files = walked_files(&args.directory, &options.walk)
+            .filter(|path| !is_output(path) && not_sidecar(path) && within_size_limits(path))
+            .inspect(|_| {
+                counter.add_total(1);
+                if let Some(progress) = &json_progress {
+                    progress.add_total(1);
+                }
+            });
+        stream_hashes(files, stored_path, &options, on_result)
+    } else {
+        stream_hashes(&files, stored_path, &options, on_result)
+    };
+
+    counter.finish();
+    if let Some(percent) = &percent {
+        percent.finish();
+    }
+    let elapsed = started.elapsed();
+    // A streaming walk runs alongside the hashing, so the two aren't told apart
+    timer.lap(if streaming { "scan and hash" } else { "hash" });
+    if let Some(progress) = &json_progress {
+        progress.finish(serde_json::json!({
+            "files": stats.hashed + stats.reused,
+            "errors": errors.load(Ordering::Relaxed),
+            "bytes": stats.bytes,
+            "elapsed_secs": elapsed.as_secs_f64(),
+        }));
+    }
+    info!(
+        "Hashed {} bytes in {:.2}s ({:.1} MB/s)",
+        stats.bytes,
+        elapsed.as_secs_f64(),
+        throughput(stats.bytes, elapsed)
+    );
+    if args.update.is_some() || checkpoint.is_some() {
+        info!(
+            "Reused {} hashes, re-hashed {} files",
+            stats.reused, stats.hashed
+        );
+    }
+    let retried = options.hash.retried.load(Ordering::Relaxed);
+    if retried > 0 {
+        info!("{} files needed a retry to read", retried);
+    }
+    let sidecars_skipped = sidecars_skipped.load(Ordering::Relaxed);
+    if sidecars_skipped > 0 {
+        info!("Skipped {} sidecars from an earlier run", sidecars_skipped);
+    }
+    let size_skipped = size_skipped.load(Ordering::Relaxed);
+    if size_skipped > 0 {
+        info!("Skipped {} files outside the size limits", size_skipped);
+    }
+
+    if let Some(writer) = jsonl {
+        writer.finish_with_trailer()?.finish()?;
+        if let Some(output_path) = &args.output {
+            info!("Checksums written to: {}", output_path.display());
+        }
+    } else {
+        let entries = entries.into_inner().unwrap();
+        let mut checksum_file =
+            ChecksumFile::new(options.algorithm, options.hash_includes_name, entries);
+        checksum_file.key_check = args
+            .hmac_key
+            .as_deref()
+            .map(|key| key_check(key, options.algorithm));
+        checksum_file.set_encoding(args.encoding);
+        checksum_file.short = args.short;
+
+        let links = checksum_file
+            .entries
+            .iter()
+            .filter(|e| !e.kind.is_file())
+            .count();
+        let line_format = matches!(
+            args.format,
+            ManifestFormat::Coreutils
+                | ManifestFormat::Bsd
+                | ManifestFormat::Sri
+                | ManifestFormat::Csv
+        );
+        if links > 0 && line_format {
+            eprintln!(
+                "Warning: {} symlinks and directories cannot be stored in this format and were left out",
+                links
+            );
+        }
+
+        // Write output
+        let output_json = match args.format {
+            ManifestFormat::Json => serde_json::to_string_pretty(&checksum_file)?,
+            ManifestFormat::Coreutils if args.null => {
+                formats::to_coreutils_null(&checksum_file.entries)
+            }
+            ManifestFormat::Coreutils => formats::to_coreutils(&checksum_file.entries),
+            ManifestFormat::Bsd => {
+                formats::to_bsd(&checksum_file.algorithm, &checksum_file.entries)
+            }
+            ManifestFormat::Sri => {
+                formats::to_sri(&checksum_file.algorithm, &checksum_file.entries)?
+            }
+            ManifestFormat::Csv => formats::to_csv(&checksum_file.entries)?,
+            ManifestFormat::Jsonl => unreachable!("JSONL output is written while hashing"),
+        };
+
+        if args.sidecar {
+            let written = write_sidecars(args, &checksum_file)?;
+            info!("Wrote {} sidecar files", written);
+        }
+
+        if let Some(output_path) = &args.output {
+            let contents = compress(output_json.as_bytes(), Compression::from_path(output_path))?;
+            write_output(output_path, &contents, !args.no_atomic)?;
+            info!("Checksums written to: {}", output_path.display());
+        } else if args.find_duplicates.is_some() || args.sidecar {
+            // The duplicate groups take stdout instead, and sidecars replace the manifest
+        } else if output_json.ends_with('\n') || args.null {
+            print!("{}", output_json);
+        } else {
+            println!("{}", output_json);
+        }
+
+        if let Some(format) = args.find_duplicates {
+            print_duplicates(&checksum_file, options.algorithm, format)?;
+        }
+    }
+    timer.lap("write");
+    info!("Time: {}", timer);
+
+    let error_count = errors.load(Ordering::Relaxed);
+    if error_count > 0 {
+        eprintln!("Warning: {} errors occurred during processing", error_count);
+    }
+    // With the output written, the checkpoint is only needed to retry failures
+    match (checkpoint, &args.checkpoint) {
+        (Some(checkpoint), Some(path)) if error_count > 0 => {
+            checkpoint.finish()?;
+            info!("Checkpoint kept at {} to retry them", path.display());
+        }
+        (Some(checkpoint), _) => checkpoint.remove()?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Writes `<file>.<algorithm>` next to every hashed file, holding a single
+/// coreutils line that names the file without its directory. Files that
+/// can't take a sidecar, e.g. in read-only directories, are reported one by
+/// one and then fail the run. Returns how many sidecars were written.
+fn write_sidecars(args: &Args, checksum_file: &ChecksumFile) -> Result<usize, RunError> {
+    let mut written = 0;
+    let mut failed = 0;
+    let hashed = checksum_file
+        .entries
+        .iter()
+        .filter(|entry| entry.kind.is_file() && entry.hash != ASSUMED_UNCHANGED_HASH);
+    for entry in hashed {
+        let path = entry_path(&args.directory, &entry.path);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let line = formats::to_coreutils(&[ChecksumEntry {
+            path: name.into_owned(),
+            ..entry.clone()
+        }]);
+        let mut sidecar = path.into_os_string();
+        sidecar.push(format!(".{}", checksum_file.algorithm));
+        let sidecar = PathBuf::from(sidecar);
+        match write_output(&sidecar, line.as_bytes(), !args.no_atomic) {
+            Ok(()) => written += 1,
+            Err(e) => {
+                eprintln!("Cannot write sidecar {}: {}", sidecar.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} sidecar files could not be written", failed).into());
+    }
+    Ok(written)
+}
+
+/// Truncates a file entry's hash to `length` hex characters, for --short.
+fn shorten(mut entry: ChecksumEntry, length: Option<usize>) -> ChecksumEntry {
+    if let Some(length) = length {
+        if entry.kind.is_file() && entry.hash != ASSUMED_UNCHANGED_HASH {
+            entry.hash.truncate(length);
+            for (_, hash) in entry.hashes.iter_mut().flatten() {
+                hash.truncate(length);
+            }
+        }
+    }
+    entry
+}
+
+/// Dry-run generation: prints the files that would be hashed and how many
+/// bytes that is, without reading any file contents.
+fn list_planned_files(
+    args: &Args,
+    files: &[PathBuf],
+    options: &GenerateOptions,
+) -> Result<(), RunError> {
+    let mut bytes = 0;
+    let mut relative_paths = Vec::with_capacity(files.len());
+    for path in files {
+        let relative = relative_path(path, &args.directory);
+        let recorded_only = (!options.walk.follow_symlinks && path.is_symlink())
+            || options
+                .assume_unchanged
+                .iter()
+                .any(|p| p.matches(&relative));
+        if !recorded_only {
+            bytes += fs::metadata(path).map(|m| if m.is_file() { m.len() } else { 0 })?;
+        }
+        relative_paths.push(relative);
+    }
+    relative_paths.sort();
+    for path in &relative_paths {
+        println!("{}", path);
+    }
+    eprintln!(
+        "Dry run: {} files, {} bytes would be hashed",
+        files.len(),
+        bytes
+    );
+    Ok(())
+}
+
+/// Prints groups of files that share a hash to stdout, with a count on stderr.
+fn print_duplicates(
+    checksum_file: &ChecksumFile,
+    algorithm: HashAlgorithm,
+    format: DuplicatesFormat,
+) -> Result<(), RunError> {
+    if !algorithm.is_cryptographic() {
+        eprintln!(
+            "Warning: {} collisions are possible; confirm with a cryptographic algorithm before deleting anything",
+            checksum_file.algorithm
+        );
+    }
+
+    let groups = checksum_file.duplicates();
+    match format {
+        DuplicatesFormat::Text => {
+            for (hash, paths) in &groups {
+                println!("{}", hash);
+                for path in paths {
+                    println!("  {}", path);
+                }
+            }
+        }
+        DuplicatesFormat::Json => {
+            let groups: Vec<_> = groups
+                .iter()
+                .map(|(hash, paths)| serde_json::json!({ "hash": hash, "paths": paths }))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&groups)?);
+        }
+    }
+
+    let redundant: usize = groups.iter().map(|(_, paths)| paths.len() - 1).sum();
+    info!(
+        "Found {} groups of duplicates ({} redundant files)",
+        groups.len(),
+        redundant
+    );
+    Ok(())
+}
+
+/// Loads the entries of an earlier checksum file for --update, which must have
+/// been hashed the same way as this run.
+fn load_previous(
+    path: &Path,
+    options: &GenerateOptions,
+    short: Option<usize>,
+) -> Result<HashMap<String, ChecksumEntry>, RunError> {
+    let previous =
+        load_manifest(path, ManifestFormat::Json, options.algorithm).map_err(load_error)?;
+    let algorithm = algorithm_name(options.algorithm);
+    if previous.algorithm != algorithm {
+        return Err(format!(
+            "{} uses {} but this run uses {}",
+            path.display(),
+            previous.algorithm,
+            algorithm
+        )
+        .into());
+    }
+    previous
+        .check_key(options.hash.key.as_deref())
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let encoding = previous
+        .hash_encoding()
+        .map_err(|e| RunError::Parse(e.into()))?;
+    if encoding != options.hash.encoding {
+        return Err(format!(
+            "{} stores {} hashes but this run writes {}",
+            path.display(),
+            encoding.name(),
+            options.hash.encoding.name()
+        )
+        .into());
+    }
+    if previous.hash_includes_name.unwrap_or(false) != options.hash_includes_name {
+        return Err(format!(
+            "{} and this run disagree on --hash-includes-name",
+            path.display()
+        )
+        .into());
+    }
+    if previous.short != short {
+        return Err(format!("{} and this run disagree on --short", path.display()).into());
+    }
+    info!(
+        "Updating {} ({} entries)",
+        path.display(),
+        previous.entries.len()
+    );
+    Ok(previous
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect())
+}
+
+/// Destination of a JSONL checksum file streamed while hashing.
+enum JsonlSink {
+    File(Encoder<io::BufWriter<OutputFile>>),
+    Stdout(io::BufWriter<io::Stdout>),
+}
+
+impl JsonlSink {
+    /// Completes the output, moving an atomically written file into place.
+    fn finish(self) -> io::Result<()> {
+        match self {
+            JsonlSink::File(encoder) => encoder
+                .finish()?
+                .into_inner()
+                .map_err(io::IntoInnerError::into_error)?
+                .commit(),
+            JsonlSink::Stdout(mut stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Write for JsonlSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            JsonlSink::File(encoder) => encoder.write(buf),
+            JsonlSink::Stdout(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            JsonlSink::File(encoder) => encoder.flush(),
+            JsonlSink::Stdout(stdout) => stdout.flush(),
+        }
+    }
+}
+
+/// Aggregate hashing rate in MB/s (10^6 bytes per second).
+fn throughput(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / 1_000_000.0 / secs
+    } else {
+        0.0
+    }
+}
+
+/// Keeps files, in the requested order, until the next one would push the
+/// cumulative size past `budget`.
+fn apply_size_budget(files: Vec<PathBuf>, budget: u64, order: BudgetOrder) -> Vec<PathBuf> {
+    let mut sized: Vec<(PathBuf, u64)> = files
+        .into_iter()
+        .map(|path| {
+            let len = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            (path, len)
+        })
+        .collect();
+
+    match order {
+        BudgetOrder::Smallest => sized.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0))),
+        BudgetOrder::Largest => sized.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))),
+        BudgetOrder::Path => sized.sort_by(|a, b| a.0.cmp(&b.0)),
+    }
+
+    let total_files = sized.len();
+    let total_bytes: u64 = sized.iter().map(|(_, len)| len).sum();
+    let mut used = 0;
+    let mut included = Vec::new();
+    for (path, len) in sized {
+        if used + len > budget {
+            break;
+        }
+        used += len;
+        included.push(path);
+    }
+
+    info!(
+        "Size budget: included {} files ({} bytes), skipped {} files ({} bytes)",
+        included.len(),
+        used,
+        total_files - included.len(),
+        total_bytes - used
+    );
+    included
+}
+
+/// Prints a summary of a checksum file in any format dirverify reads.
+fn print_manifest_info(args: &Args, checksum_path: &Path) -> Result<(), RunError> {
+    let requested = args.input_format.unwrap_or(args.format);
+    let format = manifest_format(checksum_path, requested)?;
+    let checksum_file =
+        load_manifest(checksum_path, format, args.algorithm.into()).map_err(load_error)?;
+    let entries = &checksum_file.entries;
+
+    let sizes: Vec<u64> = entries.iter().filter_map(|e| e.size).collect();
+    let with_modified = entries.iter().filter(|e| e.modified.is_some()).count();
+
+    println!("File:       {}", checksum_path.display());
+    if let Some(name) = format.to_possible_value() {
+        println!("Format:     {}", name.get_name());
+    }
+    println!("Version:    {}", checksum_file.version);
+    if checksum_file.key_check.is_some() {
+        println!(
+            "Algorithm:  HMAC-{} (verifying needs --hmac-key)",
+            checksum_file.algorithm
+        );
+    } else {
+        println!("Algorithm:  {}", checksum_file.algorithm);
+    }
+    if let Some(encoding) = &checksum_file.encoding {
+        println!("Encoding:   {}", encoding);
+    }
+    if let Some(short) = checksum_file.short {
+        println!("Short:      hashes cut to {} characters", short);
+    }
+    if checksum_file.hash_includes_name == Some(true) {
+        println!("Names:      each hash covers its path (--hash-includes-name)");
+    }
+    if let Some(manifest_hash) = &checksum_file.manifest_hash {
+        println!("Self-check: manifest_hash {}", manifest_hash);
+    }
+    match checksum_file.count {
+        Some(count) => println!("Entries:    {} (declared {})", entries.len(), count),
+        None => println!("Entries:    {}", entries.len()),
+    }
+    println!(
+        "Total size: {} bytes ({} of {} entries record a size)",
+        sizes.iter().sum::<u64>(),
+        sizes.len(),
+        entries.len()
+    );
+    println!(
+        "Metadata:   {}",
+        if with_modified == 0 && sizes.is_empty() {
+            "none"
+        } else if with_modified == entries.len() && sizes.len() == entries.len() {
+            "size and modification time"
+        } else if with_modified == 0 && sizes.len() == entries.len() {
+            "size"
+        } else {
+            "partial"
+        }
+    );
+
+    Ok(())
+}
+
+fn verify_checksums(
+    args: &Args,
+    checksum_path: &Path,
+) -> Result<(), RunError> {
+    let mut timer = PhaseTimer::start();
+    let remote_url = checksum_path.to_str().filter(|path| remote::is_url(path));
+    let format = args.input_format.unwrap_or(args.format);
+    let coreutils = format == ManifestFormat::Coreutils;
+    if args.null && args.input_format.is_some() && !coreutils {
+        return Err("--null only reads coreutils checksum files".into());
+    }
+    let from_stdin = checksum_path == Path::new("-");
+    if from_stdin && args.null {
+        return Err("--null cannot read a checksum file from stdin".into());
+    }
+    // JSONL files are checked a chunk at a time as they are read, unless an
+    // option needs every entry before or after the run
+    let needs_all_entries = args.verify_manifest
+        || args.plan.is_some()
+        || args.only.is_some()
+        || args.dry_run
+        || args.progress_percent
+        || args.detect_added
+        || args.report.is_some();
+    let streaming = remote_url.is_none()
+        && !from_stdin
+        && !args.null
+        && !needs_all_entries
+        && manifest_format(checksum_path, format)? == ManifestFormat::Jsonl;
+
+    let mut stream = None;
+    let mut checksum_file = match remote_url {
+        Some(url) => {
+            info!("Fetching checksum file: {}", url);
+            remote::fetch_checksum_file(url)
+        }
+        // A pipe can't be reopened, so its format is sniffed as it is read
+        None if from_stdin => read_manifest(io::stdin().lock(), format, args.algorithm.into()),
+        None if streaming => compression::open(checksum_path)
+            .map_err(Into::into)
+            .and_then(jsonl::stream_jsonl)
+            .map(|(header, entries)| {
+                stream = Some(entries.peekable());
+                header
+            }),
+        None if args.null => load_null_terminated(checksum_path, args.algorithm.into()),
+        None => load_manifest(checksum_path, format, args.algorithm.into()),
+    }
+    .map_err(load_error)?;
+
+    checksum_file.check_key(args.hmac_key.as_deref())?;
+
+    if args.verify_manifest {
+        let recorded = checksum_file
+            .manifest_hash
+            .as_ref()
+            .ok_or("The checksum file has no manifest_hash to verify")?;
+        if checksum_file.compute_manifest_hash().as_ref() != Some(recorded) {
+            eprintln!(
+                "FAILED: manifest_hash does not match the entries; the checksum file was modified"
+            );
+            return Err(RunError::Mismatch);
+        }
+        info!("Manifest hash verified");
+    }
+
+    if let Some(plan_path) = &args.plan {
+        let planned: BTreeSet<String> = read_plan(plan_path)?.into_iter().collect();
+        let known: BTreeSet<&String> = checksum_file.entries.iter().map(|e| &e.path).collect();
+        let unknown = planned.iter().filter(|path| !known.contains(path)).count();
+        if unknown > 0 {
+            return Err(format!(
+                "Plan lists {} paths that are not in the checksum file",
+                unknown
+            )
+            .into());
+        }
+        checksum_file
+            .entries
+            .retain(|entry| planned.contains(&entry.path));
+        info!("Using file list from plan: {}", plan_path.display());
+    }
+
+    if let Some(only) = &args.only {
+        let only = only.strip_prefix("./").unwrap_or(only);
+        let pattern = Pattern::new(only).ok();
+        checksum_file.entries.retain(|entry| {
+            entry.path == only || pattern.as_ref().is_some_and(|p| p.matches(&entry.path))
+        });
+        if checksum_file.entries.is_empty() {
+            return Err(format!("No entry in the checksum file matches '{}'", only).into());
+        }
+    }
+
+    let subpath = args.subpath.as_deref().map(normalize_subpath);
+    if subpath == Some("") {
+        return Err("--subpath needs a directory below the root".into());
+    }
+    let selected = |path: &str| match subpath {
+        Some(subpath) => is_under(path, subpath),
+        None => true,
+    };
+    let mut not_selected = 0;
+    if let Some(subpath) = subpath {
+        let before = checksum_file.entries.len();
+        checksum_file.entries.retain(|entry| selected(&entry.path));
+        not_selected = before - checksum_file.entries.len();
+        if checksum_file.entries.is_empty() && stream.is_none() {
+            return Err(format!("No entry in the checksum file is under '{}'", subpath).into());
+        }
+    }
+
+    // Absolute entries resolve on their own, so a root would be ignored. A
+    // streamed file is judged by its first entry.
+    let first_streamed = stream
+        .as_mut()
+        .and_then(|entries| entries.peek())
+        .and_then(|entry| entry.as_ref().ok());
+    let absolute = checksum_file
+        .entries
+        .iter()
+        .chain(first_streamed)
+        .any(|entry| Path::new(&entry.path).is_absolute());
+    if absolute && args.root.is_some() {
+        return Err(
+            "This checksum file stores absolute paths, which cannot be combined with --root".into(),
+        );
+    }
+    if absolute && args.detect_added {
+        return Err("--detect-added needs a checksum file with relative paths".into());
+    }
+
+    // A wrong root would otherwise report every entry as missing
+    let root_dir = args.root.as_ref().unwrap_or(&args.directory);
+    if !absolute && !root_dir.is_dir() {
+        let problem = if root_dir.exists() {
+            "is not a directory"
+        } else {
+            "does not exist"
+        };
+        return Err(format!("Root directory {} {}", root_dir.display(), problem).into());
+    }
+
+    if streaming {
+        info!(
+            "Verifying files as they are read using {} algorithm",
+            checksum_file.algorithm
+        );
+    } else {
+        info!("Verifying {} files using {} algorithm", 
+              checksum_file.entries.len(), 
+              checksum_file.algorithm);
+    }
+
+    if args.dry_run {
+        return check_coverage(args, &checksum_file.entries, root_dir);
+    }
+
+    let total = checksum_file.entries.len();
+    let completed = AtomicUsize::new(0);
+    let percent = args
+        .progress_percent
+        .then(|| PercentProgress::new(total, args.progress_stream));
+    let json_progress =
+        (args.progress == ProgressFormat::Json).then(|| JsonProgress::new("verify", total));
+    let interval = if args.verbose { 0 } else { 100 };
+    let counter = if args.quiet || json_progress.is_some() {
+        FileCounter::quiet("Verified", total)
+    } else {
+        FileCounter::new("Verified", total, interval, percent.is_none())
+    };
+    // Failed paths are appended as they are found, so the list survives an interrupted run
+    let failures_out = match &args.failures_out {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Some(Mutex::new(io::LineWriter::new(file)))
+        }
+        None => None,
+    };
+    let failures_out_error = Mutex::new(None);
+    let failures = AtomicUsize::new(0);
+    let print_failure = |path: &str, msg: &str| {
+        let shown = failures.fetch_add(1, Ordering::Relaxed);
+        if args.max_printed_failures == 0 || shown < args.max_printed_failures {
+            counter.println(&format!(
+                "\n{} {} - {}",
+                paint("FAILED:", Color::Red),
+                path,
+                msg
+            ));
+        }
+    };
+
+    // Parse algorithm from checksum file
+    let algorithm = parse_algorithm(&checksum_file.algorithm).unwrap_or_else(|| {
+        eprintln!(
+            "Warning: Unknown algorithm '{}', using SHA256",
+            checksum_file.algorithm
+        );
+        HashAlgorithm::Sha256
+    });
+    let verify_options = VerifyOptions {
+        algorithm,
+        hash_includes_name: checksum_file.hash_includes_name.unwrap_or(false),
+        skip_newer: args.skip_newer,
+        changed_since: args.changed_since,
+        size_only: args.size_only,
+        verify_ads: args.verify_ads,
+        verify_caps: args.verify_caps,
+        assume_unchanged: compile_patterns(&args.assume_unchanged),
+        allow_missing: args.allow_missing,
+        fail_fast: args.fail_fast,
+        ignore_case: args.ignore_case,
+        allow_special: args.allow_special,
+        short: checksum_file.short,
+        hash: HashOptions {
+            encoding: checksum_file
+                .hash_encoding()
+                .map_err(|e| RunError::Parse(e.into()))?,
+            ..hash_options(args)
+        },
+    };
+
+    let on_result = |entry: &ChecksumEntry, result: &VerifyResult| {
+        if let Some(percent) = &percent {
+            percent.update(completed.fetch_add(1, Ordering::Relaxed) + 1);
+        }
+        if let Some(progress) = &json_progress {
+            // Only hashed entries are read; the rest count no bytes
+            let hashed =
+                !args.size_only && matches!(result, VerifyResult::Ok | VerifyResult::Mismatch(_));
+            progress.update(if hashed { entry.size.unwrap_or(0) } else { 0 });
+        }
+
+        counter.inc();
+        if let Some(writer) = &failures_out {
+            if result.is_failure(args.allow_missing) {
+                if let Err(e) = writeln!(writer.lock().unwrap(), "{}", entry.path) {
+                    failures_out_error.lock().unwrap().get_or_insert(e);
+                }
+            }
+        }
+        match result {
+            VerifyResult::Ok => {
+                if args.verbose {
+                    counter.println(&format!("{} {}", paint("OK:", Color::Green), entry.path));
+                }
+            }
+            VerifyResult::Missing(msg)
+            | VerifyResult::Mismatch(msg)
+            | VerifyResult::Failed(msg) => print_failure(&entry.path, msg),
+            VerifyResult::Skipped(msg) => {
+                if args.verbose {
+                    counter.println(&format!(
+                        "{} {} - {}",
+                        paint("SKIPPED:", Color::Yellow),
+                        entry.path,
+                        msg
+                    ));
+                }
+            }
+            VerifyResult::Assumed => {
+                if args.verbose {
+                    counter.println(&format!("ASSUMED: {}", entry.path));
+                }
+            }
+        }
+    };
+
+    // Verify files in parallel
+    timer.lap("load");
+    let started = Instant::now();
+    let mut read_error = None;
+    let (summary, report) = match stream {
+        Some(entries) => {
+            let entries = entries
+                .map_while(|entry| match entry {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        read_error = Some(e);
+                        None
+                    }
+                })
+                .filter(|entry| {
+                    if !selected(&entry.path) {
+                        not_selected += 1;
+                        return false;
+                    }
+                    counter.add_total(1);
+                    if let Some(progress) = &json_progress {
+                        progress.add_total(1);
+                    }
+                    true
+                });
+            let summary = verify_stream(entries, root_dir, &verify_options, on_result);
+            (summary, None)
+        }
+        None => {
+            let report = verify_with(&checksum_file, root_dir, &verify_options, on_result);
+            (report.summary(), Some(report))
+        }
+    };
+    let elapsed = started.elapsed();
+    timer.lap("verify");
+
+    counter.finish();
+    if let Some(percent) = &percent {
+        percent.finish();
+    }
+    if let Some(e) = failures_out_error.into_inner().unwrap() {
+        return Err(format!("Cannot write --failures-out: {}", e).into());
+    }
+    if let Some(e) = read_error {
+        return Err(load_error(
+            format!("{}: {}", checksum_path.display(), e).into(),
+        ));
+    }
+    if let Some(subpath) = subpath.filter(|_| summary.total() == 0) {
+        return Err(format!("No entry in the checksum file is under '{}'", subpath).into());
+    }
+
+    for (path, found) in &summary.case_differences {
+        eprintln!(
+            "Warning: {} was found as {} (names differ in case)",
+            path,
+            found.display()
+        );
+    }
+
+    let mut added = Vec::new();
+    if args.detect_added && !summary.aborted {
+        let mut skip = vec![checksum_path];
+        skip.extend(args.report.as_deref());
+        skip.extend(args.failures_out.as_deref());
+        added = find_added(&checksum_file, root_dir, &walk_options(args), &skip)?;
+        // Files matched with --ignore-case already have an entry
+        let resolved: BTreeSet<String> = summary
+            .case_differences
+            .iter()
+            .map(|(_, found)| relative_path(found, root_dir))
+            .collect();
+        added.retain(|path| !resolved.contains(path) && selected(path));
+        for path in &added {
+            print_failure(path, "Not in checksum file");
+        }
+        timer.lap("scan");
+    }
+    let added_count = added.len();
+
+    let failure_count = failures.load(Ordering::Relaxed);
+    if args.max_printed_failures > 0 && failure_count > args.max_printed_failures {
+        eprintln!(
+            "... and {} more failures (use --max-printed-failures 0 or --report to list all)",
+            failure_count - args.max_printed_failures
+        );
+    }
+
+    // Summary
+    let ok_count = summary.ok;
+    let missing_count = summary.missing;
+    let mismatch_count = summary.mismatch;
+    let error_count = summary.failed;
+    let skip_count = summary.skipped;
+    let assumed_count = summary.assumed;
+    let retried = verify_options.hash.retried.load(Ordering::Relaxed);
+    // A streamed file's size is only known once it has been read
+    let total = summary.total();
+
+    let passed = summary.passed() && added_count == 0 && !(args.strict && skip_count > 0);
+    let counts = ReportCounts {
+        ok: ok_count,
+        assumed: assumed_count,
+        missing: missing_count,
+        mismatch: mismatch_count,
+        error: error_count,
+        skipped: skip_count,
+        added: args.detect_added.then_some(added_count),
+        not_selected: subpath.map(|_| not_selected),
+        total,
+    };
+    if let Some(progress) = &json_progress {
+        progress.finish(serde_json::json!({
+            "passed": passed,
+            "aborted": summary.aborted,
+            "counts": counts,
+            "bytes": summary.bytes,
+            "elapsed_secs": elapsed.as_secs_f64(),
+        }));
+    }
+
+    // With --quiet a clean run prints nothing
+    if !(args.quiet && passed) {
+        eprintln!("\nSummary:");
+        eprintln!("  OK:       {}", ok_count);
+        if assumed_count > 0 {
+            eprintln!("  Assumed:  {}", assumed_count);
+        }
+        if retried > 0 {
+            eprintln!("  Retried:  {}", retried);
+        }
+        eprintln!("  Missing:  {}", missing_count);
+        eprintln!("  Mismatch: {}", mismatch_count);
+        eprintln!("  Error:    {}", error_count);
+        eprintln!("  Skipped:  {}", skip_count);
+        if subpath.is_some() {
+            eprintln!("  Not selected: {}", not_selected);
+        }
+        if args.detect_added {
+            eprintln!("  Added:    {}", added_count);
+        }
+        eprintln!("  Total:    {}", total);
+        eprintln!(
+            "  Bytes:    {} ({:.1} MB/s)",
+            summary.bytes,
+            throughput(summary.bytes, elapsed)
+        );
+        eprintln!("  Time:     {}", timer);
+    }
+
+    if let (Some(report_path), Some(report)) = (&args.report, &report) {
+        let json = serde_json::to_string_pretty(&build_report(report, &added, counts, passed))?;
+        write_output(report_path, json.as_bytes(), !args.no_atomic)?;
+        info!("Report written to: {}", report_path.display());
+    }
+
+    if !passed {
+        return Err(RunError::Mismatch);
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct ReportCounts {
+    ok: usize,
+    assumed: usize,
+    missing: usize,
+    mismatch: usize,
+    error: usize,
+    skipped: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added: Option<usize>,
+    /// Entries outside --subpath, which were not checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_selected: Option<usize>,
+    total: usize,
+}
+
+#[derive(Serialize)]
+struct ReportEntry<'a> {
+    path: &'a str,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+/// Machine-readable outcome of a verify run, as written by --report.
+#[derive(Serialize)]
+struct Report<'a> {
+    passed: bool,
+    /// Whether --fail-fast stopped the run early
+    aborted: bool,
+    counts: ReportCounts,
+    entries: Vec<ReportEntry<'a>>,
+}
+
+/// Serializable status and reason of a verify result.
+fn report_status(result: &VerifyResult) -> (&'static str, Option<&str>) {
+    match result {
+        VerifyResult::Ok => ("ok", None),
+        VerifyResult::Missing(msg) => ("missing", Some(msg)),
+        VerifyResult::Mismatch(msg) => ("mismatch", Some(msg)),
+        VerifyResult::Failed(msg) => ("error", Some(msg)),
+        VerifyResult::Skipped(msg) => ("skipped", Some(msg)),
+        VerifyResult::Assumed => ("assumed", None),
+    }
+}
+
+fn build_report<'a>(
+    report: &'a VerifyReport,
+    added: &'a [String],
+    counts: ReportCounts,
+    passed: bool,
+) -> Report<'a> {
+    let mut entries: Vec<ReportEntry> = report
+        .results
+        .iter()
+        .map(|(path, result)| {
+            let (status, reason) = report_status(result);
+            ReportEntry {
+                path,
+                status,
+                reason,
+            }
+        })
+        .collect();
+    entries.extend(added.iter().map(|path| ReportEntry {
+        path,
+        status: "added",
+        reason: Some("Not in checksum file"),
+    }));
+
+    Report {
+        passed,
+        aborted: report.aborted,
+        counts,
+        entries,
+    }
+}
+
+/// A --subpath as stored entry paths spell it: `/`-separated, without a
+/// leading `./` or trailing `/`.
+fn normalize_subpath(subpath: &str) -> &str {
+    let subpath = subpath.strip_prefix("./").unwrap_or(subpath);
+    let subpath = subpath.trim_end_matches('/');
+    if subpath == "." {
+        ""
+    } else {
+        subpath
+    }
+}
+
+/// Whether the entry `path` is `directory` itself or lies below it.
+fn is_under(path: &str, directory: &str) -> bool {
+    path.strip_prefix(directory)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Dry-run verification: reports which entries are present on disk and
+/// whether recorded sizes match, without reading any file contents.
+fn check_coverage(args: &Args, entries: &[ChecksumEntry], root_dir: &Path) -> Result<(), RunError> {
+    let present = AtomicUsize::new(0);
+    let missing = AtomicUsize::new(0);
+    let size_mismatch = AtomicUsize::new(0);
+
+    entries.par_iter().for_each(|entry| {
+        let full_path = entry_path(root_dir, &entry.path);
+        match fs::metadata(&full_path) {
+            Ok(metadata) => match entry.size {
+                Some(size) if size != metadata.len() => {
+                    size_mismatch.fetch_add(1, Ordering::Relaxed);
+                    eprintln!(
+                        "SIZE MISMATCH: {} - expected {} bytes, found {}",
+                        entry.path,
+                        size,
+                        metadata.len()
+                    );
+                }
+                _ => {
+                    present.fetch_add(1, Ordering::Relaxed);
+                    if args.verbose {
+                        eprintln!("PRESENT: {}", entry.path);
+                    }
+                }
+            },
+            Err(_) => {
+                missing.fetch_add(1, Ordering::Relaxed);
+                eprintln!("MISSING: {}", entry.path);
+            }
+        }
+    });
+
+    let missing_count = missing.load(Ordering::Relaxed);
+    let mismatch_count = size_mismatch.load(Ordering::Relaxed);
+
+    eprintln!("\nDry run summary (no files were hashed):");
+    eprintln!("  Present:       {}", present.load(Ordering::Relaxed));
+    eprintln!("  Missing:       {}", missing_count);
+    eprintln!("  Size mismatch: {}", mismatch_count);
+    eprintln!("  Total:         {}", entries.len());
+
+    if (missing_count > 0 && !args.allow_missing) || mismatch_count > 0 {
+        return Err(RunError::Mismatch);
+    }
+
+    Ok(())
+}
+
+/// Hashes every file under `directory`, keyed by relative path.
+fn hash_directory(
+    directory: &Path,
+    algorithm: HashAlgorithm,
+    walk_options: &WalkOptions,
+    hash_includes_name: bool,
+    options: &HashOptions,
+) -> BTreeMap<String, String> {
+    collect_files(directory, walk_options)
+        .par_iter()
+        .filter_map(|path| {
+            let relative_path = relative_path(path, directory);
+            let hash = hash_walked(
+                path,
+                &relative_path,
+                walk_options,
+                algorithm,
+                hash_includes_name,
+                options,
+            );
+            match hash {
+                Ok(hash) => Some((relative_path, hash)),
+                Err(e) => {
+                    eprintln!("Error processing {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Hashes a path found by [`collect_files`] for comparison. Unfollowed
+/// symlinks compare by target and tracked empty directories all hash to "".
+fn hash_walked(
+    path: &Path,
+    relative_path: &str,
+    walk_options: &WalkOptions,
+    algorithm: HashAlgorithm,
+    hash_includes_name: bool,
+    options: &HashOptions,
+) -> io::Result<String> {
+    if !walk_options.follow_symlinks && path.is_symlink() {
+        link_target(path)
+    } else if path.is_dir() {
+        Ok(String::new())
+    } else {
+        hash_entry(path, relative_path, algorithm, hash_includes_name, options)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreeWayStatus {
+    Unchanged,
+    ChangedInA,
+    ChangedInB,
+    ChangedInBoth,
+}
+
+fn classify_three_way(base: Option<&str>, a: Option<&str>, b: Option<&str>) -> ThreeWayStatus {
+    match (a != base, b != base) {
+        (false, false) => ThreeWayStatus::Unchanged,
+        (true, false) => ThreeWayStatus::ChangedInA,
+        (false, true) => ThreeWayStatus::ChangedInB,
+        (true, true) => ThreeWayStatus::ChangedInBoth,
+    }
+}
+
+/// Loads one side of a three-way comparison, hashing it if it is a directory.
+fn load_three_way_side(
+    path: &Path,
+    base: &ChecksumFile,
+    algorithm: HashAlgorithm,
+    walk_options: &WalkOptions,
+    options: &HashOptions,
+) -> Result<BTreeMap<String, String>, RunError> {
+    let hash_includes_name = base.hash_includes_name.unwrap_or(false);
+
+    if path.is_dir() {
+        info!("Hashing directory: {}", path.display());
+        return Ok(hash_directory(
+            path,
+            algorithm,
+            walk_options,
+            hash_includes_name,
+            options,
+        ));
+    }
+
+    let checksum_file = load_checksum_file(path).map_err(load_error)?;
+    if checksum_file.algorithm != base.algorithm {
+        return Err(format!(
+            "{} uses {} but the base manifest uses {}",
+            path.display(),
+            checksum_file.algorithm,
+            base.algorithm
+        )
+        .into());
+    }
+    if checksum_file.hash_encoding() != base.hash_encoding() {
+        return Err(format!(
+            "{} and the base manifest use different hash encodings",
+            path.display()
+        )
+        .into());
+    }
+    if checksum_file.hash_includes_name.unwrap_or(false) != hash_includes_name {
+        return Err(format!(
+            "{} and the base manifest disagree on --hash-includes-name",
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(checksum_file
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path, entry.hash))
+        .collect())
+}
+
+fn three_way_compare(args: &Args, paths: &[PathBuf]) -> Result<(), RunError> {
+    let base_file = load_checksum_file(&paths[0]).map_err(load_error)?;
+    let algorithm = parse_algorithm(&base_file.algorithm).ok_or_else(|| {
+        RunError::Parse(
+            format!(
+                "Unknown algorithm '{}' in base manifest",
+                base_file.algorithm
+            )
+            .into(),
+        )
+    })?;
+    let walk_options = walk_options(args);
+
+    let options = HashOptions {
+        encoding: base_file
+            .hash_encoding()
+            .map_err(|e| RunError::Parse(e.into()))?,
+        ..hash_options(args)
+    };
+
+    let load_side =
+        |path: &Path| load_three_way_side(path, &base_file, algorithm, &walk_options, &options);
+    let a = load_side(&paths[1])?;
+    let b = load_side(&paths[2])?;
+    let base: BTreeMap<String, String> = base_file
+        .entries
+        .into_iter()
+        .map(|entry| (entry.path, entry.hash))
+        .collect();
+
+    let all_paths: BTreeSet<&String> = base.keys().chain(a.keys()).chain(b.keys()).collect();
+
+    let mut unchanged = 0;
+    let mut changed_a = 0;
+    let mut changed_b = 0;
+    let mut conflicts = 0;
+
+    for path in &all_paths {
+        let side_a = a.get(*path).map(String::as_str);
+        let side_b = b.get(*path).map(String::as_str);
+
+        match classify_three_way(base.get(*path).map(String::as_str), side_a, side_b) {
+            ThreeWayStatus::Unchanged => {
+                unchanged += 1;
+                if args.verbose {
+                    eprintln!("UNCHANGED: {}", path);
+                }
+            }
+            ThreeWayStatus::ChangedInA => {
+                changed_a += 1;
+                println!("CHANGED-A: {}", path);
+            }
+            ThreeWayStatus::ChangedInB => {
+                changed_b += 1;
+                println!("CHANGED-B: {}", path);
+            }
+            ThreeWayStatus::ChangedInBoth => {
+                conflicts += 1;
+                if side_a == side_b {
+                    println!("CONFLICT: {} (identical on both sides)", path);
+                } else {
+                    println!("CONFLICT: {}", path);
+                }
+            }
+        }
+    }
+
+    eprintln!("\nSummary:");
+    eprintln!("  Unchanged:   {}", unchanged);
+    eprintln!("  Changed (A): {}", changed_a);
+    eprintln!("  Changed (B): {}", changed_b);
+    eprintln!("  Conflicts:   {}", conflicts);
+    eprintln!("  Total:       {}", all_paths.len());
+
+    if conflicts > 0 {
+        return Err(RunError::Mismatch);
+    }
+
+    Ok(())
+}
+
+/// Compares two checksum files entry by entry, printing the paths that
+/// differ to stdout and the counts to stderr.
+fn diff_manifests(args: &Args, paths: &[PathBuf]) -> Result<(), RunError> {
+    let format = args.input_format.unwrap_or(args.format);
+    let load = |path: &Path| load_manifest(path, format, args.algorithm.into()).map_err(load_error);
+    let (a, b) = (load(&paths[0])?, load(&paths[1])?);
+
+    // Hashes made differently never match, which would list every path
+    let describe = |file: &ChecksumFile| -> Result<(String, HashEncoding, bool), RunError> {
+        let encoding = file
+            .hash_encoding()
+            .map_err(|e| RunError::Parse(e.into()))?;
+        let includes_name = file.hash_includes_name.unwrap_or(false);
+        Ok((file.algorithm.clone(), encoding, includes_name))
+    };
+    let (algorithm_a, encoding_a, names_a) = describe(&a)?;
+    let (algorithm_b, encoding_b, names_b) = describe(&b)?;
+    if algorithm_a != algorithm_b {
+        return Err(format!(
+            "{} uses {} but {} uses {}; their hashes cannot be compared",
+            paths[0].display(),
+            algorithm_a,
+            paths[1].display(),
+            algorithm_b
+        )
+        .into());
+    }
+    if encoding_a != encoding_b {
+        return Err(format!(
+            "{} stores {} hashes but {} stores {}",
+            paths[0].display(),
+            encoding_a.name(),
+            paths[1].display(),
+            encoding_b.name()
+        )
+        .into());
+    }
+    if names_a != names_b {
+        return Err("Only one of the checksum files mixes paths into its hashes".into());
+    }
+
+    let diff = a.diff(&b);
+    for path in &diff.added {
+        println!("ADDED: {}", path);
+    }
+    for path in &diff.removed {
+        println!("REMOVED: {}", path);
+    }
+    for path in &diff.changed {
+        println!("CHANGED: {}", path);
+    }
+
+    let total = diff.unchanged + diff.changed.len() + diff.added.len() + diff.removed.len();
+    eprintln!("\nSummary:");
+    eprintln!("  Unchanged: {}", diff.unchanged);
+    eprintln!("  Changed:   {}", diff.changed.len());
+    eprintln!("  Added:     {}", diff.added.len());
+    eprintln!("  Removed:   {}", diff.removed.len());
+    eprintln!("  Total:     {}", total);
+
+    if let Some(report_path) = &args.report {
+        let report = serde_json::json!({
+            "identical": diff.is_empty(),
+            "counts": {
+                "unchanged": diff.unchanged,
+                "changed": diff.changed.len(),
+                "added": diff.added.len(),
+                "removed": diff.removed.len(),
+                "total": total,
+            },
+            "added": diff.added,
+            "removed": diff.removed,
+            "changed": diff.changed,
+        });
+        let json = serde_json::to_string_pretty(&report)?;
+        write_output(report_path, json.as_bytes(), !args.no_atomic)?;
+        info!("Report written to: {}", report_path.display());
+    }
+
+    if !diff.is_empty() {
+        return Err(RunError::Mismatch);
+    }
+
+    Ok(())
+}
+
+/// Hashes the files present under both roots and reports which differ and
+/// which exist on one side only.
+fn compare_directories(args: &Args, other: &Path) -> Result<(), RunError> {
+    for dir in [args.directory.as_path(), other] {
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", dir.display()).into());
+        }
+    }
+    eprintln!(
+        "Comparing {} (A) with {} (B)",
+        args.directory.display(),
+        other.display()
+    );
+
+    let walk_options = walk_options(args);
+    let list = |dir: &Path| -> BTreeSet<String> {
+        collect_files(dir, &walk_options)
+            .iter()
+            .map(|path| relative_path(path, dir))
+            .collect()
+    };
+    let a = list(&args.directory);
+    let b = list(other);
+
+    let algorithm: HashAlgorithm = args.algorithm.into();
+    let options = hash_options(args);
+    let common: Vec<&String> = a.intersection(&b).collect();
+    let results: Vec<(&String, std::io::Result<(String, String)>)> = common
+        .par_iter()
+        .map(|path| {
+            let hash = |root: &Path| {
+                let path = entry_path(root, path);
+                hash_walked(&path, "", &walk_options, algorithm, false, &options)
+            };
+            let hashes = hash(&args.directory).and_then(|hash_a| Ok((hash_a, hash(other)?)));
+            (*path, hashes)
+        })
+        .collect();
+
+    let mut ok = 0;
+    let mut differ = Vec::new();
+    let mut errors = 0;
+    for (path, result) in &results {
+        match result {
+            Ok((hash_a, hash_b)) if hash_a == hash_b => {
+                ok += 1;
+                if args.verbose {
+                    eprintln!("OK: {}", path);
+                }
+            }
+            Ok((hash_a, hash_b)) => {
+                println!("DIFFER: {}", path);
+                differ.push(serde_json::json!({
+                    "path": path,
+                    "hash_a": hash_a,
+                    "hash_b": hash_b,
+                }));
+            }
+            Err(e) => {
+                errors += 1;
+                eprintln!("ERROR: {} - {}", path, e);
+            }
+        }
+    }
+    let only_a: Vec<&String> = a.difference(&b).collect();
+    for path in &only_a {
+        println!("ONLY-IN-A: {}", path);
+    }
+    let only_b: Vec<&String> = b.difference(&a).collect();
+    for path in &only_b {
+        println!("ONLY-IN-B: {}", path);
+    }
+
+    let total = a.union(&b).count();
+    eprintln!("\nSummary:");
+    eprintln!("  OK:        {}", ok);
+    eprintln!("  Differ:    {}", differ.len());
+    eprintln!("  Only in A: {}", only_a.len());
+    eprintln!("  Only in B: {}", only_b.len());
+    eprintln!("  Error:     {}", errors);
+    eprintln!("  Total:     {}", total);
+
+    let identical = differ.is_empty() && errors == 0 && only_a.is_empty() && only_b.is_empty();
+    if let Some(report_path) = &args.compare_report {
+        let report = serde_json::json!({
+            "identical": identical,
+            "algorithm": algorithm_name(algorithm),
+            "counts": {
+                "ok": ok,
+                "differ": differ.len(),
+                "only_in_a": only_a.len(),
+                "only_in_b": only_b.len(),
+                "errors": errors,
+                "total": total,
+            },
+            "differ": differ,
+            "only_in_a": only_a,
+            "only_in_b": only_b,
+        });
+        let json = serde_json::to_string_pretty(&report)?;
+        write_output(report_path, json.as_bytes(), !args.no_atomic)?;
+        info!("Report written to: {}", report_path.display());
+    }
+
+    if !identical {
+        return Err(RunError::Mismatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_size;
+
+    #[test]
+    fn parses_plain_and_binary_sizes() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("1K"), Ok(1024));
+        assert_eq!(parse_size("1k"), Ok(1024));
+        assert_eq!(parse_size("1KiB"), Ok(1024));
+        assert_eq!(parse_size("1MiB"), Ok(1 << 20));
+        assert_eq!(parse_size("100M"), Ok(100 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("3 TiB"), Ok(3 << 40));
+    }
+
+    #[test]
+    fn parses_decimal_sizes() {
+        assert_eq!(parse_size("1KB"), Ok(1_000));
+        assert_eq!(parse_size("5mb"), Ok(5_000_000));
+        assert_eq!(parse_size("2GB"), Ok(2_000_000_000));
+        assert_eq!(parse_size("1TB"), Ok(1_000_000_000_000));
+    }
+
+    #[test]
+    fn rejects_malformed_sizes() {
+        for value in ["", "M", "-1K", "1.5G", "10X", "1KK"] {
+            assert!(parse_size(value).is_err(), "{} should not parse", value);
         }
-        Err(e) => VerifyResult::Failed(format!("Cannot compute hash: {}", e)),
+        assert!(parse_size("20000000T").is_err());
     }
 }
@@ -0,0 +1,553 @@
+use crate::caps::Capabilities;
+use crate::compression;
+use crate::formats::{self, ManifestFormat};
+use crate::hashing::{hash_bytes, key_check, HashAlgorithm, HashEncoding};
+use crate::jsonl;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Placeholder hash recorded for files matched by --assume-unchanged.
+pub const ASSUMED_UNCHANGED_HASH: &str = "assumed-unchanged";
+
+/// What a checksum entry describes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A regular file; `hash` is its content digest
+    #[default]
+    File,
+    /// A symlink that was recorded instead of followed; `link_target` (and,
+    /// for older readers, `hash`) is its target
+    Symlink,
+    /// An empty directory, recorded with an empty `hash`
+    Directory,
+}
+
+impl EntryKind {
+    pub fn is_file(&self) -> bool {
+        *self == EntryKind::File
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChecksumEntry {
+    pub path: String,
+    pub hash: String,
+    /// Algorithm of `hash` when it differs from the checksum file's own,
+    /// e.g. while a manifest is migrated from MD5 to SHA256
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+    /// Digests by further algorithms computed in the same read, keyed by
+    /// algorithm name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hashes: Option<BTreeMap<String, String>>,
+    /// Omitted for regular files
+    #[serde(default, skip_serializing_if = "EntryKind::is_file")]
+    pub kind: EntryKind,
+    /// Where a symlink entry points. `hash` holds the same target, for
+    /// readers that predate this field and files written before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hashes of NTFS alternate data streams, keyed by stream name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ads: Option<BTreeMap<String, String>>,
+    /// File capabilities; empty sets mean the file had none when recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caps: Option<Capabilities>,
+    /// Unix permission bits, checked on verify whenever present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Hashed with every carriage return removed (--text-normalize), so
+    /// the file matches whether its lines end in CRLF or LF
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub text: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChecksumFile {
+    pub version: String,
+    pub algorithm: String,
+    /// Number of entries written, used to detect truncated manifests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    /// Whether each hash covers the relative path followed by the content
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_includes_name: Option<bool>,
+    /// Hash of the entries, see [`ChecksumFile::compute_manifest_hash`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest_hash: Option<String>,
+    /// Set when the hashes are HMACs; see [`key_check`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_check: Option<String>,
+    /// How the entries' hashes are written; absent means lowercase hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Length the file hashes were cut to with --short; only then is a
+    /// recorded hash compared as a prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short: Option<usize>,
+    /// Set for JSONL files, whose `manifest_hash` covers the entries in
+    /// file order, since they are written before they could be sorted
+    #[serde(skip)]
+    pub manifest_hash_in_file_order: bool,
+    pub entries: Vec<ChecksumEntry>,
+}
+
+impl ChecksumFile {
+    /// Builds a manifest from `entries`, sorted by path for consistent output.
+    pub fn new(
+        algorithm: HashAlgorithm,
+        hash_includes_name: bool,
+        mut entries: Vec<ChecksumEntry>,
+    ) -> Self {
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut file = ChecksumFile {
+            version: "1.0".to_string(),
+            algorithm: algorithm_name(algorithm),
+            count: Some(entries.len()),
+            hash_includes_name: hash_includes_name.then_some(true),
+            manifest_hash: None,
+            key_check: None,
+            encoding: None,
+            short: None,
+            manifest_hash_in_file_order: false,
+            entries,
+        };
+        file.manifest_hash = file.compute_manifest_hash();
+        file
+    }
+
+    /// Hashes the entries, sorted by path and serialized as compact JSON,
+    /// with the file's algorithm. Identical entries always give the same
+    /// hash. A JSONL file's entries are hashed as the lines they are
+    /// written as, in file order. `None` if the algorithm is unknown.
+    pub fn compute_manifest_hash(&self) -> Option<String> {
+        let algorithm = parse_algorithm(&self.algorithm)?;
+        if self.manifest_hash_in_file_order {
+            let mut lines = Vec::new();
+            for entry in &self.entries {
+                lines.extend(jsonl::entry_line(entry).ok()?);
+            }
+            return Some(hash_bytes(&lines, algorithm));
+        }
+        let mut entries: Vec<&ChecksumEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let json = serde_json::to_vec(&entries).ok()?;
+        Some(hash_bytes(&json, algorithm))
+    }
+
+    /// Checks that `key` is the HMAC key the hashes were made with, or that
+    /// there is no key when the hashes are plain.
+    pub fn check_key(&self, key: Option<&[u8]>) -> Result<(), String> {
+        // A prefix of an HMAC would let a forged file pass
+        if self.key_check.is_some() && self.short.is_some() {
+            return Err("The checksum file holds HMACs, which cannot be shortened".to_string());
+        }
+        match (key, &self.key_check) {
+            (None, None) => Ok(()),
+            (None, Some(_)) => Err(
+                "The checksum file holds HMACs; pass the key it was written with via --hmac-key"
+                    .to_string(),
+            ),
+            (Some(_), None) => {
+                Err("--hmac-key was given, but the checksum file holds plain hashes".to_string())
+            }
+            (Some(key), Some(expected)) => {
+                let matches = parse_algorithm(&self.algorithm)
+                    .filter(|algorithm| algorithm.supports_hmac())
+                    .is_some_and(|algorithm| key_check(key, algorithm) == *expected);
+                if matches {
+                    Ok(())
+                } else {
+                    Err(
+                        "--hmac-key does not match the key the checksum file was written with"
+                            .to_string(),
+                    )
+                }
+            }
+        }
+    }
+
+    /// The encoding of the entries' hashes.
+    pub fn hash_encoding(&self) -> Result<HashEncoding, String> {
+        match &self.encoding {
+            None => Ok(HashEncoding::Hex),
+            Some(name) => {
+                HashEncoding::parse(name).ok_or_else(|| format!("Unknown hash encoding '{}'", name))
+            }
+        }
+    }
+
+    /// Records `encoding` in the header; hex, the default, is left implicit.
+    pub fn set_encoding(&mut self, encoding: HashEncoding) {
+        self.encoding = (encoding != HashEncoding::Hex).then(|| encoding.name().to_string());
+    }
+
+    /// Paths of regular files that share a hash, as `(hash, paths)` groups
+    /// ordered by their first path. Empty files and --assume-unchanged
+    /// placeholders are left out.
+    pub fn duplicates(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut by_hash: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for entry in &self.entries {
+            if entry.kind.is_file() && entry.size != Some(0) && entry.hash != ASSUMED_UNCHANGED_HASH
+            {
+                by_hash.entry(&entry.hash).or_default().push(&entry.path);
+            }
+        }
+        let mut groups: Vec<_> = by_hash
+            .into_iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .collect();
+        groups.sort_by(|a, b| a.1[0].cmp(b.1[0]));
+        groups
+    }
+
+    /// Compares entries with those of `newer` by path, without reading any
+    /// files. Hashes are compared as recorded, so both checksum files need
+    /// the same algorithm and encoding for the result to mean anything.
+    pub fn diff(&self, newer: &ChecksumFile) -> ManifestDiff {
+        let by_path = |file: &ChecksumFile| -> BTreeMap<String, (EntryKind, String)> {
+            file.entries
+                .iter()
+                .map(|entry| (entry.path.clone(), (entry.kind, entry.hash.clone())))
+                .collect()
+        };
+        let old = by_path(self);
+        let mut new = by_path(newer);
+
+        let mut diff = ManifestDiff::default();
+        for (path, recorded) in old {
+            match new.remove(&path) {
+                None => diff.removed.push(path),
+                Some(current) if current != recorded => diff.changed.push(path),
+                Some(_) => diff.unchanged += 1,
+            }
+        }
+        diff.added = new.into_keys().collect();
+        diff
+    }
+}
+
+/// Differences between two checksum files, as found by
+/// [`ChecksumFile::diff`]. Paths are sorted.
+#[derive(Debug, Default)]
+pub struct ManifestDiff {
+    /// Paths only in the newer file
+    pub added: Vec<String>,
+    /// Paths only in the older file
+    pub removed: Vec<String>,
+    /// Paths in both whose hash or kind differs
+    pub changed: Vec<String>,
+    /// Paths recorded the same way in both
+    pub unchanged: usize,
+}
+
+impl ManifestDiff {
+    /// Whether both files record the same paths with the same hashes.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Reads a JSON checksum file, decompressing `.gz` and `.zst` files.
+pub fn load_checksum_file(checksum_path: &Path) -> Result<ChecksumFile, Box<dyn Error>> {
+    read_checksum_file(compression::open(checksum_path)?)
+}
+
+/// Parses a JSON checksum file from `reader`, checking its declared count.
+pub(crate) fn read_checksum_file<R: Read>(reader: R) -> Result<ChecksumFile, Box<dyn Error>> {
+    let checksum_file: ChecksumFile = serde_json::from_reader(reader)?;
+
+    if let Some(count) = checksum_file.count {
+        if count != checksum_file.entries.len() {
+            return Err(format!(
+                "Checksum file declares {} entries but contains {}; it may be truncated",
+                count,
+                checksum_file.entries.len()
+            )
+            .into());
+        }
+    }
+
+    Ok(checksum_file)
+}
+
+/// Format of the checksum file at `path`: the one `requested` or implied by
+/// its extension, or, when that gives plain JSON, the one its first line
+/// looks like.
+pub fn manifest_format(path: &Path, requested: ManifestFormat) -> io::Result<ManifestFormat> {
+    let format = formats::detect(&compression::uncompressed_path(path), requested);
+    if format == ManifestFormat::Json {
+        return Ok(formats::sniff(&first_line(path)?));
+    }
+    Ok(format)
+}
+
+/// Loads a checksum file in the `requested` format, or the one its name or
+/// first line implies. Coreutils and CSV files don't name their algorithm,
+/// so it comes from the file name or `default_algorithm`; BSD and SRI files
+/// carry it in every line.
+pub fn load_manifest(
+    path: &Path,
+    requested: ManifestFormat,
+    default_algorithm: HashAlgorithm,
+) -> Result<ChecksumFile, Box<dyn Error>> {
+    let format = manifest_format(path, requested)?;
+    match format {
+        ManifestFormat::Json => load_checksum_file(path),
+        ManifestFormat::Jsonl => jsonl::read_jsonl(compression::open(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e).into()),
+        _ => parse_lines(&read_text(path)?, format, path, default_algorithm)
+            .map_err(|e| format!("{}: {}", path.display(), e).into()),
+    }
+}
+
+/// Reads a checksum file from `reader`, such as a pipe, that can't be
+/// reopened to guess its format. The first [`SNIFF_BYTES`] are buffered to
+/// sniff it, unless a format other than JSON is `requested`, and then read
+/// again ahead of the rest. Coreutils and CSV files use `default_algorithm`.
+pub fn read_manifest<R: Read>(
+    mut reader: R,
+    requested: ManifestFormat,
+    default_algorithm: HashAlgorithm,
+) -> Result<ChecksumFile, Box<dyn Error>> {
+    let mut head = Vec::new();
+    reader.by_ref().take(SNIFF_BYTES).read_to_end(&mut head)?;
+    let format = if requested == ManifestFormat::Json {
+        formats::sniff(&first_nonempty_line(&head))
+    } else {
+        requested
+    };
+    let mut reader = io::Cursor::new(head).chain(reader);
+    match format {
+        ManifestFormat::Json => read_checksum_file(io::BufReader::new(reader)),
+        ManifestFormat::Jsonl => jsonl::read_jsonl(io::BufReader::new(reader)),
+        _ => {
+            let mut text = String::new();
+            reader.read_to_string(&mut text)?;
+            Ok(parse_lines(
+                &text,
+                format,
+                Path::new(""),
+                default_algorithm,
+            )?)
+        }
+    }
+}
+
+/// Parses a checksum file in one of the line-based formats. Coreutils and
+/// CSV files don't name their algorithm, so it comes from `path` or
+/// `default_algorithm`; BSD and SRI files carry it in every line.
+fn parse_lines(
+    text: &str,
+    format: ManifestFormat,
+    path: &Path,
+    default_algorithm: HashAlgorithm,
+) -> Result<ChecksumFile, String> {
+    match format {
+        ManifestFormat::Coreutils => {
+            let entries = formats::parse_coreutils(text)?;
+            Ok(coreutils_file(path, default_algorithm, entries))
+        }
+        ManifestFormat::Csv => {
+            let entries = formats::parse_csv(text)?;
+            Ok(coreutils_file(path, default_algorithm, entries))
+        }
+        ManifestFormat::Bsd | ManifestFormat::Sri => {
+            let (algorithm, entries) = if format == ManifestFormat::Sri {
+                formats::parse_sri(text)?
+            } else {
+                formats::parse_bsd(text)?
+            };
+            Ok(ChecksumFile {
+                version: "1.0".to_string(),
+                algorithm,
+                count: None,
+                hash_includes_name: None,
+                manifest_hash: None,
+                key_check: None,
+                encoding: None,
+                short: None,
+                manifest_hash_in_file_order: false,
+                entries,
+            })
+        }
+        ManifestFormat::Json | ManifestFormat::Jsonl => {
+            unreachable!("JSON checksum files are not line-based")
+        }
+    }
+}
+
+/// Loads a coreutils checksum file whose records end with NUL bytes, as
+/// written by `sha256sum -z`. The algorithm is found as in [`load_manifest`].
+pub fn load_null_terminated(
+    path: &Path,
+    default_algorithm: HashAlgorithm,
+) -> Result<ChecksumFile, Box<dyn Error>> {
+    let entries = formats::parse_coreutils_null(&read_text(path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(coreutils_file(path, default_algorithm, entries))
+}
+
+/// A checksum file for coreutils or CSV `entries`, with the algorithm
+/// implied by the file's name or else `default_algorithm`.
+fn coreutils_file(
+    path: &Path,
+    default_algorithm: HashAlgorithm,
+    entries: Vec<ChecksumEntry>,
+) -> ChecksumFile {
+    ChecksumFile {
+        version: "1.0".to_string(),
+        algorithm: formats::algorithm_from_name(path)
+            .unwrap_or_else(|| algorithm_name(default_algorithm)),
+        count: None,
+        hash_includes_name: None,
+        manifest_hash: None,
+        key_check: None,
+        encoding: None,
+        short: None,
+        manifest_hash_in_file_order: false,
+        entries,
+    }
+}
+
+/// Bytes read from the start of a checksum file to guess its format.
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+/// The first non-empty line among the first [`SNIFF_BYTES`] of the
+/// decompressed file; longer lines come back cut short.
+fn first_line(path: &Path) -> io::Result<String> {
+    let mut head = Vec::new();
+    compression::open(path)?
+        .take(SNIFF_BYTES)
+        .read_to_end(&mut head)?;
+    Ok(first_nonempty_line(&head))
+}
+
+fn first_nonempty_line(head: &[u8]) -> String {
+    let head = String::from_utf8_lossy(head);
+    let line = head.lines().find(|line| !line.trim().is_empty());
+    line.unwrap_or_default().to_string()
+}
+
+fn read_text(path: &Path) -> io::Result<String> {
+    let mut text = String::new();
+    compression::open(path)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+pub fn parse_algorithm(name: &str) -> Option<HashAlgorithm> {
+    match name {
+        "sha256" => Some(HashAlgorithm::Sha256),
+        "sha384" => Some(HashAlgorithm::Sha384),
+        "sha512" => Some(HashAlgorithm::Sha512),
+        "md5" => Some(HashAlgorithm::Md5),
+        "crc32" => Some(HashAlgorithm::Crc32),
+        "blake2" => Some(HashAlgorithm::Blake2),
+        "blake3" => Some(HashAlgorithm::Blake3),
+        "xxh3" => Some(HashAlgorithm::Xxh3),
+        "xxh64" => Some(HashAlgorithm::Xxh64),
+        _ => None,
+    }
+}
+
+/// The name stored in a checksum file's `algorithm` field; the inverse of
+/// [`parse_algorithm`]. Spelled out so that renaming a variant can't change
+/// what existing manifests are matched against.
+pub fn algorithm_name(algorithm: HashAlgorithm) -> String {
+    let name = match algorithm {
+        HashAlgorithm::Sha256 => "sha256",
+        HashAlgorithm::Sha384 => "sha384",
+        HashAlgorithm::Sha512 => "sha512",
+        HashAlgorithm::Md5 => "md5",
+        HashAlgorithm::Crc32 => "crc32",
+        HashAlgorithm::Blake2 => "blake2",
+        HashAlgorithm::Blake3 => "blake3",
+        HashAlgorithm::Xxh3 => "xxh3",
+        HashAlgorithm::Xxh64 => "xxh64",
+    };
+    name.to_string()
+}
+
+/// Writes `contents` to `path`. When `atomic` is set the data goes to
+/// `<path>.tmp` first and is renamed over the target, so an interrupted run
+/// never leaves a partial file or clobbers the previous one.
+pub fn write_output(path: &Path, contents: &[u8], atomic: bool) -> io::Result<()> {
+    if !atomic {
+        return fs::write(path, contents);
+    }
+
+    let mut output = OutputFile::create(path, true)?;
+    output.write_all(contents)?;
+    output.commit()
+}
+
+/// Where atomic output for `path` is written before being renamed into
+/// place: the same path with `.tmp` appended.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    PathBuf::from(tmp_path)
+}
+
+/// An output file written incrementally, with the same `atomic` behavior as
+/// [`write_output`]. Dropping it without calling [`OutputFile::commit`]
+/// removes the temporary file.
+pub struct OutputFile {
+    file: Option<File>,
+    path: PathBuf,
+    /// Where writes go until `commit` renames it over `path`
+    tmp_path: Option<PathBuf>,
+}
+
+impl OutputFile {
+    pub fn create(path: &Path, atomic: bool) -> io::Result<Self> {
+        let tmp_path = atomic.then(|| temp_path(path));
+        let file = File::create(tmp_path.as_deref().unwrap_or(path))?;
+        Ok(OutputFile {
+            file: Some(file),
+            path: path.to_path_buf(),
+            tmp_path,
+        })
+    }
+
+    /// Syncs the data and, for atomic output, moves it into place.
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("file is open until commit");
+        if let Some(tmp_path) = &self.tmp_path {
+            file.sync_all()?;
+            // Close before renaming; Windows can't rename an open file
+            drop(file);
+            fs::rename(tmp_path, &self.path)?;
+            self.tmp_path = None;
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("file is open").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("file is open").flush()
+    }
+}
+
+impl Drop for OutputFile {
+    fn drop(&mut self) {
+        self.file.take();
+        if let Some(tmp_path) = &self.tmp_path {
+            let _ = fs::remove_file(tmp_path);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Unix permission bits, recorded on request and compared when present.
+
+use std::fs::Metadata;
+
+/// The permission bits of a file, including setuid, setgid and sticky, or
+/// `None` on platforms without them.
+#[cfg(unix)]
+pub fn file_mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+/// Size of each range read issued by the prefetch workers.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Reads a file through several concurrent range reads while handing the
+/// bytes back strictly in order, so an order-dependent hasher can consume
+/// them sequentially.
+///
+/// Worker `k` reads chunks `k`, `k + n`, `k + 2n`, ... into its own bounded
+/// channel; the reader drains the channels round-robin. Dropping the reader
+/// closes the channels, which stops the workers and releases the file.
+pub struct PrefetchReader {
+    channels: Vec<Receiver<io::Result<Vec<u8>>>>,
+    total_chunks: u64,
+    next_chunk: u64,
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl PrefetchReader {
+    pub fn new(file: File, len: u64, workers: usize) -> Self {
+        let file = Arc::new(file);
+        let total_chunks = len.div_ceil(CHUNK_SIZE);
+        let stride = workers as u64;
+
+        let channels = (0..stride)
+            .map(|worker| {
+                let (sender, receiver) = sync_channel(2);
+                let file = Arc::clone(&file);
+                thread::spawn(move || {
+                    let mut index = worker;
+                    while index < total_chunks {
+                        let offset = index * CHUNK_SIZE;
+                        let size = CHUNK_SIZE.min(len - offset) as usize;
+                        let result = read_chunk(&file, offset, size);
+                        let failed = result.is_err();
+                        if sender.send(result).is_err() || failed {
+                            break;
+                        }
+                        index += stride;
+                    }
+                });
+                receiver
+            })
+            .collect();
+
+        PrefetchReader {
+            channels,
+            total_chunks,
+            next_chunk: 0,
+            current: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for PrefetchReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.current.len() {
+            if self.next_chunk == self.total_chunks {
+                return Ok(0);
+            }
+            let channel = &self.channels[(self.next_chunk % self.channels.len() as u64) as usize];
+            self.current = channel
+                .recv()
+                .map_err(|_| io::Error::other("prefetch worker stopped unexpectedly"))??;
+            self.pos = 0;
+            self.next_chunk += 1;
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(unix)]
+fn read_chunk(file: &File, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+
+    let mut buffer = vec![0; size];
+    file.read_exact_at(&mut buffer, offset)?;
+    Ok(buffer)
+}
+
+#[cfg(windows)]
+fn read_chunk(file: &File, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+    use std::os::windows::fs::FileExt;
+
+    let mut buffer = vec![0; size];
+    let mut filled = 0;
+    while filled < size {
+        match file.seek_read(&mut buffer[filled..], offset + filled as u64)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+    Ok(buffer)
+}
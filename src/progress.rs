@@ -0,0 +1,269 @@
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProgressStream {
+    Stdout,
+    Stderr,
+}
+
+/// How progress is reported while hashing or verifying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Progress bar on stderr, or a plain counter when it isn't a terminal
+    Bar,
+    /// One JSON object per line on stdout, ending with a summary object
+    Json,
+}
+
+/// Writes progress as newline-delimited JSON events on stdout, for tools
+/// that embed dirverify. Updates from worker threads are throttled to one
+/// event per [`JsonProgress::INTERVAL`].
+pub struct JsonProgress {
+    phase: &'static str,
+    /// Grows through [`JsonProgress::add_total`] while files are still being found
+    total: AtomicUsize,
+    processed: AtomicUsize,
+    bytes: AtomicU64,
+    /// When the last event was written; `None` until the first one
+    last: Mutex<Option<Instant>>,
+}
+
+impl JsonProgress {
+    pub const INTERVAL: Duration = Duration::from_millis(200);
+
+    pub fn new(phase: &'static str, total: usize) -> Self {
+        JsonProgress {
+            phase,
+            total: AtomicUsize::new(total),
+            processed: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            last: Mutex::new(None),
+        }
+    }
+
+    pub fn add_total(&self, count: usize) {
+        self.total.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Counts one processed file of `bytes` bytes, writing an event unless
+    /// one was written within the interval.
+    pub fn update(&self, bytes: u64) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        // Another thread holding the lock is about to write an event anyway
+        let Ok(mut last) = self.last.try_lock() else {
+            return;
+        };
+        if last.is_some_and(|last| last.elapsed() < Self::INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        self.write_progress();
+    }
+
+    /// Writes the final progress event, followed by a summary event holding
+    /// the fields of `summary`.
+    pub fn finish(&self, summary: Value) {
+        let _last = self.last.lock().unwrap();
+        self.write_progress();
+        let mut event = json!({ "event": "summary", "phase": self.phase });
+        if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), summary) {
+            event.extend(fields);
+        }
+        write_event(&event);
+    }
+
+    fn write_progress(&self) {
+        write_event(&json!({
+            "event": "progress",
+            "phase": self.phase,
+            "processed": self.processed.load(Ordering::Relaxed),
+            "total": self.total.load(Ordering::Relaxed),
+            "bytes": self.bytes.load(Ordering::Relaxed),
+        }));
+    }
+}
+
+fn write_event(event: &Value) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", event).and_then(|_| stdout.flush());
+}
+
+/// Emits a bare integer completion percentage (0-100), one per line,
+/// whenever the value changes.
+pub struct PercentProgress {
+    total: usize,
+    stream: ProgressStream,
+    last: Mutex<Option<usize>>,
+}
+
+impl PercentProgress {
+    pub fn new(total: usize, stream: ProgressStream) -> Self {
+        let progress = PercentProgress {
+            total,
+            stream,
+            last: Mutex::new(None),
+        };
+        progress.update(0);
+        progress
+    }
+
+    pub fn update(&self, done: usize) {
+        let percent = (done.min(self.total) * 100)
+            .checked_div(self.total)
+            .unwrap_or(100);
+
+        // Holding the lock while writing keeps the emitted values monotonic
+        // even when updates arrive out of order from worker threads.
+        let mut last = self.last.lock().unwrap();
+        if last.is_some_and(|last| percent <= last) {
+            return;
+        }
+        *last = Some(percent);
+
+        let _ = match self.stream {
+            ProgressStream::Stdout => writeln!(io::stdout().lock(), "{}", percent),
+            ProgressStream::Stderr => writeln!(io::stderr().lock(), "{}", percent),
+        };
+    }
+
+    pub fn finish(&self) {
+        self.update(self.total);
+    }
+}
+
+/// Tracks completed files, drawing an indicatif bar with rate and ETA when
+/// stderr is a terminal and falling back to the plain `\r` counter otherwise.
+pub struct FileCounter {
+    label: &'static str,
+    /// Grows through [`FileCounter::add_total`] while files are still being found
+    total: AtomicUsize,
+    /// Plain mode prints the counter every this many files (0 = never)
+    interval: usize,
+    done: AtomicUsize,
+    bar: Option<ProgressBar>,
+    /// Set by [`FileCounter::quiet`]; only `println` output is shown
+    quiet: bool,
+}
+
+impl FileCounter {
+    pub fn new(label: &'static str, total: usize, interval: usize, allow_bar: bool) -> Self {
+        let bar = (allow_bar && io::stderr().is_terminal()).then(|| {
+            let bar = ProgressBar::new(total as u64);
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "{msg} [{elapsed_precise}] {wide_bar} {pos}/{len} files ({per_sec}, ETA {eta})",
+                )
+                .unwrap(),
+            );
+            bar.set_message(label);
+            bar
+        });
+        FileCounter {
+            label,
+            total: AtomicUsize::new(total),
+            interval,
+            done: AtomicUsize::new(0),
+            bar,
+            quiet: false,
+        }
+    }
+
+    /// A counter that draws nothing, for --quiet.
+    pub fn quiet(label: &'static str, total: usize) -> Self {
+        FileCounter {
+            label,
+            total: AtomicUsize::new(total),
+            interval: 0,
+            done: AtomicUsize::new(0),
+            bar: None,
+            quiet: true,
+        }
+    }
+
+    /// Counts `count` more files towards the total, for walks that hash
+    /// files while still finding more.
+    pub fn add_total(&self, count: usize) {
+        self.total.fetch_add(count, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc_length(count as u64);
+        }
+    }
+
+    pub fn inc(&self) {
+        let count = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        match &self.bar {
+            Some(bar) => bar.inc(1),
+            None if self.interval > 0 && count.is_multiple_of(self.interval) => {
+                let total = self.total.load(Ordering::Relaxed);
+                eprint!("\r{}: {}/{}", self.label, count, total);
+            }
+            None => {}
+        }
+    }
+
+    /// Prints a line to stderr without tearing the bar.
+    pub fn println(&self, line: &str) {
+        match &self.bar {
+            // The leading newline only exists to break the plain `\r` counter line
+            Some(bar) if !bar.is_finished() => bar.println(line.trim_start_matches('\n')),
+            _ => eprintln!("{}", line),
+        }
+    }
+
+    pub fn finish(&self) {
+        match &self.bar {
+            Some(bar) => bar.finish(),
+            None if self.quiet => {}
+            None => {
+                let total = self.total.load(Ordering::Relaxed);
+                eprintln!("\r{}: {}/{}", self.label, total, total)
+            }
+        }
+    }
+}
+
+/// Wall-clock time spent in each phase of a run, shown in the summary so a
+/// slow walk can be told apart from slow hashing.
+pub struct PhaseTimer {
+    started: Instant,
+    lap_started: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimer {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        PhaseTimer {
+            started: now,
+            lap_started: now,
+            phases: Vec::new(),
+        }
+    }
+
+    /// Ends the current phase, recording the time since the previous one
+    /// ended under `name`.
+    pub fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.lap_started));
+        self.lap_started = now;
+    }
+}
+
+/// Renders as e.g. `scan 0.12s, hash 3.40s, total 3.53s`.
+impl fmt::Display for PhaseTimer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, duration) in &self.phases {
+            write!(f, "{} {:.2}s, ", name, duration.as_secs_f64())?;
+        }
+        write!(f, "total {:.2}s", self.started.elapsed().as_secs_f64())
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::OnceLock;
+
+/// Chunks each file may have read but not yet hashed.
+const QUEUE_DEPTH: usize = 4;
+
+/// Threads that do nothing but read, shared by every [`ReadAheadReader`].
+static READ_POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
+
+/// Reads a file on the shared reader pool and hands the bytes to the
+/// hashing thread through a bounded channel.
+///
+/// Disk waits then tie up a reader thread instead of a hashing thread, and
+/// the hasher works on one chunk while the next is being read. The pool is
+/// created on first use with `read_threads` threads; later values are
+/// ignored. Dropping the reader closes the channel, which ends the read and
+/// closes the file.
+pub struct ReadAheadReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl ReadAheadReader {
+    pub fn new(mut file: File, chunk_size: usize, read_threads: usize) -> Self {
+        let (sender, receiver) = sync_channel(QUEUE_DEPTH);
+        read_pool(read_threads).spawn(move || loop {
+            let mut chunk = vec![0; chunk_size];
+            let result = file.read(&mut chunk).map(|n| {
+                chunk.truncate(n);
+                chunk
+            });
+            let last = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+            if sender.send(result).is_err() || last {
+                break;
+            }
+        });
+
+        ReadAheadReader {
+            receiver,
+            current: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for ReadAheadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.current.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.current = self
+                .receiver
+                .recv()
+                .map_err(|_| io::Error::other("reader thread stopped unexpectedly"))??;
+            self.pos = 0;
+            if self.current.is_empty() {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.current.len() - self.pos);
+        buf[..n].copy_from_slice(&self.current[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn read_pool(read_threads: usize) -> &'static rayon::ThreadPool {
+    READ_POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(read_threads)
+            .thread_name(|index| format!("dirverify-read-{}", index))
+            .build()
+            .expect("failed to start reader threads")
+    })
+}
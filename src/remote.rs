@@ -0,0 +1,46 @@
+//! Fetching checksum files over HTTP(S).
+//!
+//! Downloads need the `network` cargo feature; without it
+//! [`fetch_checksum_file`] always fails, keeping the default build free of an
+//! HTTP client.
+
+use crate::manifest::ChecksumFile;
+use std::error::Error;
+use std::io;
+
+/// Whether `location` is an `http://` or `https://` URL rather than a path.
+pub fn is_url(location: &str) -> bool {
+    let lower = location.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Downloads and parses the JSON checksum file at `url`. Failed requests
+/// and error statuses are reported as I/O errors; a response that isn't a
+/// checksum file is reported as a parse error naming the URL.
+#[cfg(feature = "network")]
+pub fn fetch_checksum_file(url: &str) -> Result<ChecksumFile, Box<dyn Error>> {
+    let fetch_error = |e: reqwest::Error| io::Error::other(format!("cannot fetch {}: {}", url, e));
+
+    let response = reqwest::blocking::get(url).map_err(fetch_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(
+            io::Error::other(format!("cannot fetch {}: server returned {}", url, status)).into(),
+        );
+    }
+    let body = response.bytes().map_err(fetch_error)?;
+    crate::manifest::read_checksum_file(&body[..])
+        .map_err(|e| format!("{} is not a valid checksum file: {}", url, e).into())
+}
+
+#[cfg(not(feature = "network"))]
+pub fn fetch_checksum_file(url: &str) -> Result<ChecksumFile, Box<dyn Error>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "cannot fetch {}: dirverify was built without the `network` feature",
+            url
+        ),
+    )
+    .into())
+}
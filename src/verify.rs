@@ -0,0 +1,655 @@
+use crate::hashing::{hash_entry_multi, link_target, HashAlgorithm, HashOptions};
+use crate::manifest::{
+    algorithm_name, parse_algorithm, ChecksumEntry, ChecksumFile, EntryKind, ASSUMED_UNCHANGED_HASH,
+};
+use crate::mode::file_mode;
+use crate::walk::{
+    absolute_in, collect_files, entry_path, matches_any, relative_path, WalkOptions,
+};
+use crate::{ads, caps};
+use glob::Pattern;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Settings shared by every entry checked in verify mode.
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Algorithm the checksum file was written with; entries that name
+    /// their own algorithm are hashed with that instead
+    pub algorithm: HashAlgorithm,
+    /// Whether the checksum file's hashes cover the relative path too
+    pub hash_includes_name: bool,
+    /// Skip files whose modification time is newer than the recorded one
+    pub skip_newer: bool,
+    /// Skip files last modified before this Unix time, going by the
+    /// recorded modification time or, without one, the file's current one
+    pub changed_since: Option<u64>,
+    /// Compare recorded sizes instead of hashing; entries without a size
+    /// are skipped
+    pub size_only: bool,
+    pub verify_ads: bool,
+    pub verify_caps: bool,
+    /// Entries whose path matches are only checked for existence
+    pub assume_unchanged: Vec<Pattern>,
+    /// Don't treat missing files as failures
+    pub allow_missing: bool,
+    /// Skip the remaining entries after the first failure; entries already
+    /// being hashed still finish
+    pub fail_fast: bool,
+    /// When an entry's path doesn't exist, look for a file whose name
+    /// differs only in case before reporting it missing
+    pub ignore_case: bool,
+    /// Hash FIFOs, sockets and devices found where a file was recorded
+    /// instead of reporting them as the wrong type
+    pub allow_special: bool,
+    /// Length the recorded hashes were cut to with --short, if they were
+    pub short: Option<usize>,
+    pub hash: HashOptions,
+}
+
+impl VerifyOptions {
+    /// Default options for verifying `file`, or `None` if its algorithm or
+    /// hash encoding is unknown.
+    pub fn for_file(file: &ChecksumFile) -> Option<Self> {
+        let hash = HashOptions {
+            encoding: file.hash_encoding().ok()?,
+            ..HashOptions::default()
+        };
+        Some(VerifyOptions {
+            algorithm: parse_algorithm(&file.algorithm)?,
+            hash_includes_name: file.hash_includes_name.unwrap_or(false),
+            skip_newer: false,
+            changed_since: None,
+            size_only: false,
+            verify_ads: false,
+            verify_caps: false,
+            assume_unchanged: Vec::new(),
+            allow_missing: false,
+            fail_fast: false,
+            ignore_case: false,
+            allow_special: false,
+            short: file.short,
+            hash,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifyResult {
+    Ok,
+    /// File listed in the checksum file is absent from the target
+    Missing(String),
+    /// File is present but its content or type differs from the record
+    Mismatch(String),
+    /// File could not be checked (I/O or metadata errors)
+    Failed(String),
+    Skipped(String),
+    /// File exists but was trusted without hashing (--assume-unchanged)
+    Assumed,
+}
+
+impl VerifyResult {
+    /// Whether this outcome fails the run.
+    pub fn is_failure(&self, allow_missing: bool) -> bool {
+        match self {
+            VerifyResult::Missing(_) => !allow_missing,
+            VerifyResult::Mismatch(_) | VerifyResult::Failed(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Outcome of verifying every entry of a checksum file.
+#[derive(Debug)]
+pub struct VerifyReport {
+    /// Relative path and result of each entry, in checksum file order
+    pub results: Vec<(String, VerifyResult)>,
+    /// Whether `fail_fast` cut the run short
+    pub aborted: bool,
+    /// Bytes read while hashing entries
+    pub bytes: u64,
+    /// Entries found only by ignoring case, with the path that was checked
+    /// instead, sorted by entry path
+    pub case_differences: Vec<(String, PathBuf)>,
+    allow_missing: bool,
+}
+
+impl VerifyReport {
+    /// Number of results for which `filter` returns true.
+    pub fn count(&self, filter: impl Fn(&VerifyResult) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, result)| filter(result))
+            .count()
+    }
+
+    /// Whether no entry failed.
+    pub fn passed(&self) -> bool {
+        !self
+            .results
+            .iter()
+            .any(|(_, result)| result.is_failure(self.allow_missing))
+    }
+
+    /// Counts of each kind of result, as [`verify_stream`] reports them.
+    pub fn summary(&self) -> VerifySummary {
+        let summary = VerifySummary {
+            aborted: self.aborted,
+            bytes: self.bytes,
+            case_differences: self.case_differences.clone(),
+            allow_missing: self.allow_missing,
+            ..VerifySummary::default()
+        };
+        self.results
+            .iter()
+            .fold(summary, |mut summary, (_, result)| {
+                summary.add(result);
+                summary
+            })
+    }
+}
+
+/// Counts of each kind of result from a verify run, without the results
+/// themselves.
+#[derive(Debug, Default)]
+pub struct VerifySummary {
+    pub ok: usize,
+    pub assumed: usize,
+    pub missing: usize,
+    pub mismatch: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Whether `fail_fast` cut the run short
+    pub aborted: bool,
+    /// Bytes read while hashing entries
+    pub bytes: u64,
+    /// Entries found only by ignoring case, with the path that was checked
+    /// instead, sorted by entry path
+    pub case_differences: Vec<(String, PathBuf)>,
+    allow_missing: bool,
+}
+
+impl VerifySummary {
+    fn add(&mut self, result: &VerifyResult) {
+        let count = match result {
+            VerifyResult::Ok => &mut self.ok,
+            VerifyResult::Assumed => &mut self.assumed,
+            VerifyResult::Missing(_) => &mut self.missing,
+            VerifyResult::Mismatch(_) => &mut self.mismatch,
+            VerifyResult::Failed(_) => &mut self.failed,
+            VerifyResult::Skipped(_) => &mut self.skipped,
+        };
+        *count += 1;
+    }
+
+    /// Number of entries checked.
+    pub fn total(&self) -> usize {
+        self.ok + self.assumed + self.missing + self.mismatch + self.failed + self.skipped
+    }
+
+    /// Whether no entry failed.
+    pub fn passed(&self) -> bool {
+        self.mismatch == 0 && self.failed == 0 && (self.allow_missing || self.missing == 0)
+    }
+}
+
+/// Entries [`verify_stream`] reads and checks at a time; only this many are
+/// held in memory at once.
+const STREAM_CHUNK: usize = 4096;
+
+/// State shared by the threads checking the entries of one run.
+struct Checker<'a> {
+    root: &'a Path,
+    options: &'a VerifyOptions,
+    // rayon can't cancel, so with fail_fast the remaining entries check this
+    // and are skipped instead
+    abort: AtomicBool,
+    bytes: AtomicU64,
+    case_differences: Mutex<Vec<(String, PathBuf)>>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(root: &'a Path, options: &'a VerifyOptions) -> Self {
+        Checker {
+            root,
+            options,
+            abort: AtomicBool::new(false),
+            bytes: AtomicU64::new(0),
+            case_differences: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn check(&self, entry: &ChecksumEntry) -> VerifyResult {
+        let options = self.options;
+        let result = if self.abort.load(Ordering::Relaxed) {
+            VerifyResult::Skipped("Not checked after an earlier failure".to_string())
+        } else {
+            let mut path = entry_path(self.root, &entry.path);
+            if options.ignore_case && fs::symlink_metadata(&path).is_err() {
+                if let Some(found) = find_ignoring_case(&path) {
+                    let mut differences = self.case_differences.lock().unwrap();
+                    differences.push((entry.path.clone(), found.clone()));
+                    path = found;
+                }
+            }
+            let (result, hashed) = verify_single_file(
+                &path,
+                entry,
+                matches_any(&entry.path, &options.assume_unchanged),
+                options,
+            );
+            self.bytes.fetch_add(hashed, Ordering::Relaxed);
+            result
+        };
+        if options.fail_fast && result.is_failure(options.allow_missing) {
+            self.abort.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Whether the run was aborted, bytes read and sorted case differences.
+    fn finish(self) -> (bool, u64, Vec<(String, PathBuf)>) {
+        let mut case_differences = self.case_differences.into_inner().unwrap();
+        case_differences.sort();
+        (
+            self.abort.into_inner(),
+            self.bytes.into_inner(),
+            case_differences,
+        )
+    }
+}
+
+/// Checks every entry of `file` against the tree at `root`.
+pub fn verify(file: &ChecksumFile, root: &Path, options: &VerifyOptions) -> VerifyReport {
+    verify_with(file, root, options, |_, _| {})
+}
+
+/// Like [`verify`], but calls `on_result` for each entry as soon as it has
+/// been checked.
+pub fn verify_with<F>(
+    file: &ChecksumFile,
+    root: &Path,
+    options: &VerifyOptions,
+    on_result: F,
+) -> VerifyReport
+where
+    F: Fn(&ChecksumEntry, &VerifyResult) + Sync,
+{
+    let checker = Checker::new(root, options);
+    let results = file
+        .entries
+        .par_iter()
+        .map(|entry| {
+            let result = checker.check(entry);
+            on_result(entry, &result);
+            (entry.path.clone(), result)
+        })
+        .collect();
+
+    let (aborted, bytes, case_differences) = checker.finish();
+    VerifyReport {
+        results,
+        aborted,
+        bytes,
+        case_differences,
+        allow_missing: options.allow_missing,
+    }
+}
+
+/// Checks entries as `entries` yields them, a chunk at a time, so a checksum
+/// file too large to load can be verified while it is read. Only counts are
+/// kept; `on_result` sees each entry as soon as it has been checked.
+pub fn verify_stream<I, F>(
+    entries: I,
+    root: &Path,
+    options: &VerifyOptions,
+    on_result: F,
+) -> VerifySummary
+where
+    I: IntoIterator<Item = ChecksumEntry>,
+    F: Fn(&ChecksumEntry, &VerifyResult) + Sync,
+{
+    let checker = Checker::new(root, options);
+    let mut summary = VerifySummary {
+        allow_missing: options.allow_missing,
+        ..VerifySummary::default()
+    };
+    let mut entries = entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        let chunk: Vec<ChecksumEntry> = entries.by_ref().take(STREAM_CHUNK).collect();
+        let results: Vec<VerifyResult> = chunk
+            .par_iter()
+            .map(|entry| {
+                let result = checker.check(entry);
+                on_result(entry, &result);
+                result
+            })
+            .collect();
+        for result in &results {
+            summary.add(result);
+        }
+    }
+
+    (summary.aborted, summary.bytes, summary.case_differences) = checker.finish();
+    summary
+}
+
+/// Finds an existing path that matches `path` when case is ignored, one
+/// component at a time. Gives up when a directory holds several names that
+/// only differ in case, since any pick would be a guess.
+fn find_ignoring_case(path: &Path) -> Option<PathBuf> {
+    if fs::symlink_metadata(path).is_ok() {
+        return Some(path.to_path_buf());
+    }
+    let parent = find_ignoring_case(path.parent()?)?;
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    let mut matches = fs::read_dir(&parent)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().to_lowercase() == name);
+    match (matches.next(), matches.next()) {
+        (Some(found), None) => Some(found.path()),
+        _ => None,
+    }
+}
+
+/// Lists files under `root` that `file` has no entry for, skipping the
+/// checksum file and the run's own outputs, such as a report, in case they
+/// live inside the tree. A path that doesn't exist locally, such as a URL,
+/// skips nothing.
+pub fn find_added(
+    file: &ChecksumFile,
+    root: &Path,
+    walk: &WalkOptions,
+    skip: &[&Path],
+) -> io::Result<Vec<String>> {
+    let known: BTreeSet<&str> = file.entries.iter().map(|e| e.path.as_str()).collect();
+    let skipped: Vec<PathBuf> = skip
+        .iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+    let canonical_root = fs::canonicalize(root)?;
+
+    let mut added: Vec<String> = collect_files(root, walk)
+        .into_iter()
+        .filter(|path| !skipped.contains(&absolute_in(path, root, &canonical_root)))
+        .map(|path| relative_path(&path, root))
+        .filter(|path| !known.contains(path.as_str()))
+        .collect();
+    added.sort();
+    Ok(added)
+}
+
+/// Checks one entry, returning its result and the number of bytes hashed.
+fn verify_single_file(
+    path: &Path,
+    entry: &ChecksumEntry,
+    assume_unchanged: bool,
+    options: &VerifyOptions,
+) -> (VerifyResult, u64) {
+    match entry.kind {
+        EntryKind::Symlink => {
+            let expected = entry.link_target.as_deref().unwrap_or(&entry.hash);
+            return (verify_link(path, expected), 0);
+        }
+        EntryKind::Directory => return (verify_empty_dir(path), 0),
+        EntryKind::File => {}
+    }
+    if let Some(since) = options.changed_since {
+        if modified_time(path, entry).is_some_and(|modified| modified < since) {
+            let result = VerifyResult::Skipped("Not modified since --changed-since".to_string());
+            return (result, 0);
+        }
+    }
+    if !path.exists() {
+        // exists() follows links, so a link whose target is gone lands here too
+        let dangling = fs::symlink_metadata(path)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+        let result = if dangling {
+            VerifyResult::Failed("dangling symlink".to_string())
+        } else {
+            VerifyResult::Missing("File not found".to_string())
+        };
+        return (result, 0);
+    }
+    let readable = path.is_file() || (options.allow_special && !path.is_dir());
+    if !readable {
+        let result = VerifyResult::Mismatch(if path.is_dir() {
+            "expected file but found directory".to_string()
+        } else {
+            "expected file but found special file".to_string()
+        });
+        return (result, 0);
+    }
+
+    if assume_unchanged || entry.hash == ASSUMED_UNCHANGED_HASH {
+        return (VerifyResult::Assumed, 0);
+    }
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return (
+                VerifyResult::Failed(format!("Cannot read metadata: {}", e)),
+                0,
+            )
+        }
+    };
+
+    // Check if we should skip newer files
+    if let (true, Some(stored_modified)) = (options.skip_newer, entry.modified) {
+        if let Ok(modified) = metadata.modified() {
+            if let Ok(duration) = modified.duration_since(SystemTime::UNIX_EPOCH) {
+                let current_modified = duration.as_secs();
+                if current_modified > stored_modified {
+                    let result = VerifyResult::Skipped("File is newer on target".to_string());
+                    return (result, 0);
+                }
+            }
+        }
+    }
+
+    if options.size_only {
+        let result = match entry.size {
+            // Converting line endings changes the size but not the hash
+            _ if entry.text => VerifyResult::Skipped(
+                "Size of a normalized text file can change with its line endings".to_string(),
+            ),
+            Some(size) if size != metadata.len() => VerifyResult::Mismatch(format!(
+                "Size mismatch: expected {} bytes, found {}",
+                size,
+                metadata.len()
+            )),
+            Some(_) => VerifyResult::Ok,
+            None => VerifyResult::Skipped("No size recorded to compare".to_string()),
+        };
+        return (result, 0);
+    }
+
+    let result = match (check_hash(path, entry, options), entry.mode) {
+        (VerifyResult::Ok, Some(expected)) => match file_mode(&metadata) {
+            Some(actual) if actual != expected => VerifyResult::Mismatch(format!(
+                "Permission mismatch: expected {:04o}, found {:04o}",
+                expected, actual
+            )),
+            _ => VerifyResult::Ok,
+        },
+        (result, _) => result,
+    };
+    (result, metadata.len())
+}
+
+/// The entry's recorded modification time, or the file's current one if
+/// none was recorded.
+fn modified_time(path: &Path, entry: &ChecksumEntry) -> Option<u64> {
+    entry.modified.or_else(|| {
+        let modified = fs::metadata(path).ok()?.modified().ok()?;
+        Some(
+            modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()?
+                .as_secs(),
+        )
+    })
+}
+
+/// Whether `actual` is the `recorded` digest, or starts with it when the
+/// checksum file says its hashes were cut to exactly that length.
+fn matches_recorded(recorded: &str, actual: &str, short: Option<usize>) -> bool {
+    recorded == actual || (short == Some(recorded.len()) && actual.starts_with(recorded))
+}
+
+fn check_hash(path: &Path, entry: &ChecksumEntry, options: &VerifyOptions) -> VerifyResult {
+    let algorithm = match &entry.algorithm {
+        Some(name) => match parse_algorithm(name) {
+            Some(algorithm) => algorithm,
+            None => return VerifyResult::Failed(format!("Unknown algorithm: {}", name)),
+        },
+        None => options.algorithm,
+    };
+    // Digests recorded with --algorithms are checked in the same read
+    let extras: Vec<(&String, &String)> = entry.hashes.iter().flatten().collect();
+    let mut algorithms = vec![algorithm];
+    for (name, _) in &extras {
+        match parse_algorithm(name) {
+            Some(algorithm) => algorithms.push(algorithm),
+            None => return VerifyResult::Failed(format!("Unknown algorithm: {}", name)),
+        }
+    }
+    let hash_options = HashOptions {
+        normalize_text: entry.text,
+        ..options.hash.clone()
+    };
+    let hashes = hash_entry_multi(
+        path,
+        &entry.path,
+        &algorithms,
+        options.hash_includes_name,
+        &hash_options,
+    );
+    match hashes {
+        Ok(hashes) => {
+            let hash = &hashes[0].1;
+            let short = options.short;
+            let matched = matches_recorded(&entry.hash, hash, short);
+            let extra_mismatch = extras
+                .iter()
+                .zip(&hashes[1..])
+                .find(|((_, expected), (_, actual))| !matches_recorded(expected, actual, short));
+            if let Some(((name, expected), (_, actual))) = extra_mismatch.filter(|_| matched) {
+                VerifyResult::Mismatch(format!(
+                    "Hash mismatch ({}): expected {}, got {}",
+                    name, expected, actual
+                ))
+            } else if matched {
+                let streams = match (options.verify_ads, &entry.ads) {
+                    (true, Some(expected)) => {
+                        verify_streams(path, expected, algorithm, &options.hash)
+                    }
+                    _ => VerifyResult::Ok,
+                };
+                match (streams, options.verify_caps, &entry.caps) {
+                    (VerifyResult::Ok, true, Some(expected)) => verify_caps(path, expected),
+                    (streams, _, _) => streams,
+                }
+            } else if options.short == Some(entry.hash.len()) {
+                VerifyResult::Mismatch(format!(
+                    "Hash mismatch: recorded hash {} is not a {} digest, nor a prefix of {}",
+                    entry.hash,
+                    algorithm_name(algorithm),
+                    hash
+                ))
+            } else if entry.hash.len() != hash.len() {
+                // The recorded digest was produced by a different algorithm
+                VerifyResult::Mismatch(format!(
+                    "Hash mismatch: recorded hash {} is not a {} digest",
+                    entry.hash,
+                    algorithm_name(algorithm)
+                ))
+            } else {
+                VerifyResult::Mismatch(format!(
+                    "Hash mismatch: expected {}, got {}",
+                    entry.hash, hash
+                ))
+            }
+        }
+        Err(e) => VerifyResult::Failed(format!("Cannot compute hash: {}", e)),
+    }
+}
+
+fn verify_link(path: &Path, expected: &str) -> VerifyResult {
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            VerifyResult::Missing("Symlink not found".to_string())
+        }
+        Err(e) => VerifyResult::Failed(format!("Cannot read metadata: {}", e)),
+        Ok(metadata) if !metadata.file_type().is_symlink() => {
+            VerifyResult::Mismatch("expected symlink but found a regular entry".to_string())
+        }
+        Ok(_) => match link_target(path) {
+            Ok(target) if target == expected => VerifyResult::Ok,
+            Ok(target) => VerifyResult::Mismatch(format!(
+                "Symlink target changed: expected {}, found {}",
+                expected, target
+            )),
+            Err(e) => VerifyResult::Failed(format!("Cannot read symlink: {}", e)),
+        },
+    }
+}
+
+fn verify_empty_dir(path: &Path) -> VerifyResult {
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            VerifyResult::Missing("Directory not found".to_string())
+        }
+        Err(e) => VerifyResult::Failed(format!("Cannot read metadata: {}", e)),
+        Ok(metadata) if !metadata.is_dir() => {
+            VerifyResult::Mismatch("expected directory but found file".to_string())
+        }
+        Ok(_) => match fs::read_dir(path) {
+            Ok(mut entries) => match entries.next() {
+                None => VerifyResult::Ok,
+                Some(_) => VerifyResult::Mismatch(
+                    "expected empty directory but it has entries".to_string(),
+                ),
+            },
+            Err(e) => VerifyResult::Failed(format!("Cannot read directory: {}", e)),
+        },
+    }
+}
+
+fn verify_streams(
+    path: &Path,
+    expected: &BTreeMap<String, String>,
+    algorithm: HashAlgorithm,
+    options: &HashOptions,
+) -> VerifyResult {
+    match ads::hash_streams(path, algorithm, options) {
+        // The target volume has no alternate streams to compare against
+        Ok(None) => VerifyResult::Ok,
+        Ok(Some(actual)) => match ads::describe_mismatch(expected, &actual) {
+            Some(msg) => VerifyResult::Mismatch(msg),
+            None => VerifyResult::Ok,
+        },
+        Err(e) => VerifyResult::Failed(format!("Cannot read alternate data streams: {}", e)),
+    }
+}
+
+fn verify_caps(path: &Path, expected: &caps::Capabilities) -> VerifyResult {
+    match caps::read_capabilities(path) {
+        // The platform has no file capabilities to compare against
+        Ok(None) => VerifyResult::Ok,
+        Ok(Some(actual)) => match caps::describe_mismatch(expected, &actual) {
+            Some(msg) => VerifyResult::Mismatch(msg),
+            None => VerifyResult::Ok,
+        },
+        Err(e) => VerifyResult::Failed(format!("Cannot read file capabilities: {}", e)),
+    }
+}
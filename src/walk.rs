@@ -0,0 +1,242 @@
+use glob::Pattern;
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Which files a directory walk picks up.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// Only files matching one of these are kept (all files if empty).
+    /// Patterns are tried against the walked path and against the path
+    /// relative to the walk root.
+    pub include: Vec<Pattern>,
+    /// Files matching any of these are skipped; takes precedence over `include`
+    pub exclude: Vec<Pattern>,
+    /// Only files whose name ends in one of these extensions are kept (all
+    /// files if empty). Given lowercase and without the leading dot; names
+    /// are compared case-insensitively, and `tar.gz` works too.
+    pub extensions: Vec<String>,
+    /// Honor .gitignore, .ignore and global git excludes
+    pub gitignore: bool,
+    /// Skip files and directories whose name starts with `.`, along with
+    /// everything inside such directories
+    pub ignore_hidden: bool,
+    /// Follow symlinks to files and directories instead of listing the links
+    /// themselves; link cycles are skipped
+    pub follow_symlinks: bool,
+    /// Also list empty directories, so they can be recorded as entries
+    pub track_dirs: bool,
+    /// Also list FIFOs, sockets and devices, which are otherwise skipped
+    /// because reading one can block forever or never reach an end
+    pub allow_special: bool,
+    /// Only descend this many levels; 1 keeps just the files directly in
+    /// the root (no limit if `None`)
+    pub max_depth: Option<usize>,
+}
+
+fn should_exclude(path: &Path, root: &Path, patterns: &[Pattern]) -> bool {
+    matches_path(path, root, patterns)
+}
+
+fn should_include(path: &Path, root: &Path, patterns: &[Pattern]) -> bool {
+    patterns.is_empty() || matches_path(path, root, patterns)
+}
+
+/// Whether a pattern matches `path` as walked or relative to `root`, so
+/// that `build/**` works regardless of where the walk started.
+fn matches_path(path: &Path, root: &Path, patterns: &[Pattern]) -> bool {
+    let full = path.to_str().map(with_forward_slashes);
+    let relative = path
+        .strip_prefix(root)
+        .ok()
+        .and_then(Path::to_str)
+        .map(with_forward_slashes);
+    patterns.iter().any(|pattern| {
+        full.as_deref().is_some_and(|s| pattern.matches(s))
+            || relative.as_deref().is_some_and(|s| pattern.matches(s))
+    })
+}
+
+/// Patterns are written with `/`, so Windows paths are matched with their
+/// `\` separators swapped for it.
+fn with_forward_slashes(path: &str) -> Cow<'_, str> {
+    if cfg!(windows) {
+        Cow::Owned(path.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Compiles glob patterns, skipping any that don't parse.
+pub fn compile_patterns(patterns: &[String]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|p| Pattern::new(p).ok())
+        .collect()
+}
+
+pub(crate) fn matches_any(relative_path: &str, patterns: &[Pattern]) -> bool {
+    let relative_path = with_forward_slashes(relative_path);
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches(&relative_path))
+}
+
+/// Lists the regular files under `directory`, plus the symlinks themselves
+/// unless `follow_symlinks` is set and empty directories if `track_dirs` is.
+/// The root itself is never listed. Paths are sorted.
+pub fn collect_files(directory: &Path, options: &WalkOptions) -> Vec<PathBuf> {
+    let files = Mutex::new(Vec::new());
+    walk_files(directory, options, |path| files.lock().unwrap().push(path));
+    let mut files = files.into_inner().unwrap();
+    files.sort();
+    files
+}
+
+/// Walks `directory` on several threads, calling `on_file` for every path
+/// [`collect_files`] would list as soon as it is found, in no particular
+/// order. With `gitignore`, git's ignore rules apply, including nested
+/// .gitignore files; hidden files are kept unless `ignore_hidden` is set,
+/// and only the `.git` directory itself is skipped.
+pub fn walk_files<F>(directory: &Path, options: &WalkOptions, on_file: F)
+where
+    F: Fn(PathBuf) + Sync,
+{
+    let mut builder = WalkBuilder::new(directory);
+    builder
+        .standard_filters(options.gitignore)
+        .hidden(options.ignore_hidden)
+        .require_git(false)
+        .max_depth(options.max_depth)
+        .follow_links(options.follow_symlinks);
+    if options.gitignore {
+        builder.filter_entry(|e| e.file_name() != ".git");
+    }
+    builder.build_parallel().run(|| {
+        Box::new(|entry| {
+            if let Ok(entry) = entry {
+                if is_listed(&entry, directory, options) {
+                    on_file(entry.into_path());
+                }
+            }
+            WalkState::Continue
+        })
+    });
+}
+
+fn is_listed(entry: &DirEntry, root: &Path, options: &WalkOptions) -> bool {
+    let kind_listed = entry.file_type().is_some_and(|t| {
+        t.is_file()
+            || t.is_symlink()
+            || (options.allow_special && !t.is_dir())
+            || (options.track_dirs && t.is_dir() && entry.depth() > 0 && is_empty_dir(entry.path()))
+    });
+    let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+    kind_listed
+        && (is_dir || has_extension(entry.path(), &options.extensions))
+        && should_include(entry.path(), root, &options.include)
+        && !should_exclude(entry.path(), root, &options.exclude)
+}
+
+/// Whether the file name of `path` ends in `.` and one of `extensions`, or
+/// `extensions` is empty.
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    if extensions.is_empty() {
+        return true;
+    }
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    extensions.iter().any(|extension| {
+        // A dotfile such as `.iso` is all stem and has no extension
+        let suffix = format!(".{}", extension);
+        name.len() > suffix.len() && name.ends_with(&suffix)
+    })
+}
+
+/// Whether `path` is a FIFO, socket or device rather than a regular file,
+/// directory or symlink. With `follow_symlinks`, a link counts as what it
+/// points to.
+pub fn is_special(path: &Path, follow_symlinks: bool) -> bool {
+    let metadata = if follow_symlinks {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    metadata.is_ok_and(|metadata| {
+        let kind = metadata.file_type();
+        !kind.is_file() && !kind.is_dir() && !kind.is_symlink()
+    })
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// The path of `path` under `root` as stored in checksum files: always with
+/// `/` separators, so files made on Windows verify elsewhere and vice versa.
+pub fn relative_path(path: &Path, root: &Path) -> String {
+    portable_path(path.strip_prefix(root).unwrap_or(path))
+}
+
+/// Renders `path` with `/` separators. Only Windows needs converting; on
+/// other platforms a `\` is an ordinary file name character.
+pub fn portable_path(path: &Path) -> String {
+    with_forward_slashes(&path.to_string_lossy()).into_owned()
+}
+
+/// Resolves a stored entry path under `root`, one component at a time.
+/// Windows also accepts `\`, as found in checksum files from older versions.
+pub fn entry_path(root: &Path, stored: &str) -> PathBuf {
+    if Path::new(stored).is_absolute() {
+        return PathBuf::from(stored);
+    }
+    let is_separator = |c: char| c == '/' || (cfg!(windows) && c == '\\');
+    let mut path = root.to_path_buf();
+    for component in stored.split(is_separator).filter(|c| !c.is_empty()) {
+        path.push(component);
+    }
+    path
+}
+
+/// Resolves `output` to an absolute path; the file itself need not exist yet.
+pub fn canonical_output_path(output: &Path) -> io::Result<PathBuf> {
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = output.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+    })?;
+    Ok(fs::canonicalize(parent)?.join(file_name))
+}
+
+/// Re-roots a path found under `root` onto `canonical_root`.
+pub fn absolute_in(path: &Path, root: &Path, canonical_root: &Path) -> PathBuf {
+    canonical_root.join(path.strip_prefix(root).unwrap_or(path))
+}
+
+/// Expresses absolute `path` relative to absolute `base`, using `..` as needed.
+pub fn path_relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path_components: Vec<_> = path.components().collect();
+    let base_components: Vec<_> = base.components().collect();
+    let common = path_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..base_components.len() {
+        relative.push("..");
+    }
+    for component in &path_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
@@ -0,0 +1,108 @@
+//! Timings for tuning rather than checks of behavior, so they are ignored
+//! by default. Run them in release mode to get meaningful numbers:
+//!
+//! ```bash
+//! cargo test --release --test benchmarks -- --ignored --nocapture
+//! ```
+
+use dirverify::hashing::{hash_file, hash_file_multi};
+use dirverify::{generate, ChecksumEntry, GenerateOptions, HashAlgorithm, HashOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// Size of the file each benchmark hashes.
+const FILE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Writes a file of `size` bytes that doesn't compress to nothing.
+fn large_file(dir: &Path, size: usize) -> PathBuf {
+    let path = dir.join("large.bin");
+    let data: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(&path, data).unwrap();
+    path
+}
+
+fn throughput(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64() / 1_000_000.0
+}
+
+#[test]
+#[ignore]
+fn bench_multi_algorithm_hashing() {
+    let dir = TempDir::new().unwrap();
+    let path = large_file(dir.path(), FILE_SIZE);
+    let algorithms = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Blake3,
+    ];
+    let options = HashOptions::default();
+
+    // Each algorithm on its own, one read per algorithm
+    let mut separate = Duration::ZERO;
+    let mut expected = Vec::new();
+    for algorithm in algorithms {
+        let started = Instant::now();
+        expected.push((algorithm, hash_file(&path, algorithm, &options).unwrap()));
+        let elapsed = started.elapsed();
+        println!(
+            "{:?} alone: {:.2}s ({:.1} MB/s)",
+            algorithm,
+            elapsed.as_secs_f64(),
+            throughput(FILE_SIZE, elapsed)
+        );
+        separate += elapsed;
+    }
+
+    // All three from one read, updated side by side
+    let started = Instant::now();
+    let digests = hash_file_multi(&path, &algorithms, &options).unwrap();
+    let combined = started.elapsed();
+    println!(
+        "sha256+sha512+blake3 in one read: {:.2}s ({:.1} MB/s), {:.2}s one after another",
+        combined.as_secs_f64(),
+        throughput(FILE_SIZE, combined),
+        separate.as_secs_f64()
+    );
+    assert_eq!(digests, expected);
+}
+
+#[test]
+#[ignore]
+fn bench_read_threads() {
+    // Many mid-sized files, so reads and hashing can overlap across files
+    let dir = TempDir::new().unwrap();
+    let files = 64;
+    for index in 0..files {
+        let path = large_file(dir.path(), FILE_SIZE / files);
+        fs::rename(path, dir.path().join(format!("file{}.bin", index))).unwrap();
+    }
+
+    let run = |read_threads: usize| {
+        let mut options = GenerateOptions::new(dir.path(), HashAlgorithm::Sha256);
+        options.hash.read_threads = read_threads;
+        let started = Instant::now();
+        let manifest = generate(&options).unwrap();
+        let elapsed = started.elapsed();
+        println!(
+            "--read-threads {}: {:.2}s ({:.1} MB/s)",
+            read_threads,
+            elapsed.as_secs_f64(),
+            throughput(FILE_SIZE, elapsed)
+        );
+        manifest.entries
+    };
+
+    // The files were just written, so both runs read from the page cache;
+    // drop it between runs to time a cold disk instead
+    let default = run(0);
+    let split = run(4);
+    let hashes = |entries: Vec<ChecksumEntry>| {
+        entries
+            .into_iter()
+            .map(|entry| (entry.path, entry.hash))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(hashes(default), hashes(split));
+}
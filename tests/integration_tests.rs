@@ -21,7 +21,7 @@ fn test_generate_checksums_basic() {
     create_test_file(dir.path(), "file2.txt", b"Test content");
     
     let output = Command::new("cargo")
-        .args(&["run", "--", dir.path().to_str().unwrap()])
+        .args(["run", "--", dir.path().to_str().unwrap()])
         .output()
         .expect("Failed to execute command");
     
@@ -48,7 +48,7 @@ fn test_verify_checksums() {
     // Generate checksums
     let checksum_file = source_dir.path().join("checksums.json");
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             source_dir.path().to_str().unwrap(),
             "-o", checksum_file.to_str().unwrap()
@@ -60,7 +60,7 @@ fn test_verify_checksums() {
     
     // Verify checksums
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             target_dir.path().to_str().unwrap(),
             "-c", checksum_file.to_str().unwrap()
@@ -81,7 +81,7 @@ fn test_exclude_patterns() {
     create_test_file(dir.path(), ".git/config", b"Exclude git");
     
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             dir.path().to_str().unwrap(),
             "-e", "*.tmp",
@@ -106,11 +106,11 @@ fn test_different_algorithms() {
     let dir = TempDir::new().unwrap();
     create_test_file(dir.path(), "test.txt", b"Test content");
     
-    let algorithms = vec!["sha256", "md5", "crc32", "blake2", "xxh3"];
+    let algorithms = vec!["sha256", "sha384", "sha512", "md5", "crc32", "blake2", "blake3", "xxh3", "xxh64"];
     
     for algo in algorithms {
         let output = Command::new("cargo")
-            .args(&[
+            .args([
                 "run", "--",
                 dir.path().to_str().unwrap(),
                 "-a", algo
@@ -136,7 +136,7 @@ fn test_verify_failed_checksum() {
     // Generate checksums
     let checksum_file = source_dir.path().join("checksums.json");
     Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             source_dir.path().to_str().unwrap(),
             "-o", checksum_file.to_str().unwrap()
@@ -146,7 +146,7 @@ fn test_verify_failed_checksum() {
     
     // Verify should fail
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             target_dir.path().to_str().unwrap(),
             "-c", checksum_file.to_str().unwrap()
@@ -168,7 +168,7 @@ fn test_subdirectories() {
     create_test_file(dir.path(), "sub1/sub2/file2.txt", b"Nested file");
     
     let output = Command::new("cargo")
-        .args(&["run", "--", dir.path().to_str().unwrap()])
+        .args(["run", "--", dir.path().to_str().unwrap()])
         .output()
         .expect("Failed to execute command");
     
@@ -186,7 +186,7 @@ fn test_verbose_mode() {
     create_test_file(dir.path(), "test.txt", b"Test");
     
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--",
             dir.path().to_str().unwrap(),
             "-v"
@@ -198,3 +198,4140 @@ fn test_verbose_mode() {
     let stderr = String::from_utf8(output.stderr).unwrap();
     assert!(stderr.contains("Processed:"));
 }
+
+#[test]
+fn test_progress_percent() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"A");
+    create_test_file(dir.path(), "b.txt", b"B");
+    let checksum_file = dir.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap(),
+            "--progress-percent",
+            "--progress-stream", "stdout"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let percents: Vec<u32> = stdout.lines().map(|l| l.parse().unwrap()).collect();
+    assert_eq!(percents.first(), Some(&0));
+    assert_eq!(percents.last(), Some(&100));
+    assert!(percents.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn test_verify_rejects_count_mismatch() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+    let checksum_file = dir.path().join("checksums.json");
+    fs::write(
+        &checksum_file,
+        r#"{"version": "1.0", "algorithm": "sha256", "count": 2, "entries": [
+            {"path": "test.txt", "hash": "532eaabd9574880dbf76b9b8cc00832c20a6ec113d682299550d7a6e0f345e25"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("declares 2 entries but contains 1"));
+}
+
+#[test]
+fn test_three_way_compare() {
+    let base_dir = TempDir::new().unwrap();
+    let a_dir = TempDir::new().unwrap();
+    let b_dir = TempDir::new().unwrap();
+
+    for dir in [&base_dir, &a_dir, &b_dir] {
+        create_test_file(dir.path(), "same.txt", b"Same");
+        create_test_file(dir.path(), "a.txt", b"Base A");
+        create_test_file(dir.path(), "b.txt", b"Base B");
+        create_test_file(dir.path(), "both.txt", b"Base both");
+    }
+    create_test_file(a_dir.path(), "a.txt", b"Changed by A");
+    create_test_file(b_dir.path(), "b.txt", b"Changed by B");
+    create_test_file(a_dir.path(), "both.txt", b"A version");
+    create_test_file(b_dir.path(), "both.txt", b"B version");
+
+    let manifests = TempDir::new().unwrap();
+    let base_manifest = manifests.path().join("base.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            base_dir.path().to_str().unwrap(),
+            "-o", base_manifest.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--three-way",
+            base_manifest.to_str().unwrap(),
+            a_dir.path().to_str().unwrap(),
+            b_dir.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to run three-way comparison");
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("CHANGED-A: a.txt"));
+    assert!(stdout.contains("CHANGED-B: b.txt"));
+    assert!(stdout.contains("CONFLICT: both.txt"));
+    assert!(!stdout.contains("same.txt"));
+}
+
+#[test]
+fn test_atomic_output_leaves_no_temp_file() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+    let out_dir = TempDir::new().unwrap();
+    let checksum_file = out_dir.path().join("checksums.json");
+    fs::write(&checksum_file, b"previous manifest").unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let contents = fs::read_to_string(&checksum_file).unwrap();
+    assert!(contents.contains("test.txt"));
+    assert!(!out_dir.path().join("checksums.json.tmp").exists());
+}
+
+#[test]
+fn test_manifest_info() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+    create_test_file(dir.path(), "b.txt", b"123");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "md5",
+            "--skip-newer",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Algorithm:  md5"));
+    assert!(stdout.contains("Entries:    2"));
+    assert!(stdout.contains("Total size: 8 bytes"));
+    assert!(stdout.contains("size and modification time"));
+}
+
+#[test]
+fn test_manifest_info_on_hmac_file() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--hmac-key", "secret",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Algorithm:  HMAC-sha256 (verifying needs --hmac-key)"));
+}
+
+#[test]
+fn test_manifest_info_shows_header_fields() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+    let manifests = TempDir::new().unwrap();
+
+    let info = |extra: &[&str]| {
+        let checksum_file = manifests.path().join("checksums.json");
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+
+        let output = Command::new("cargo")
+            .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+            .output()
+            .expect("Failed to run info");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let stdout = info(&[]);
+    assert!(!stdout.contains("Encoding:"));
+    assert!(!stdout.contains("Short:"));
+    assert!(!stdout.contains("Names:"));
+
+    let stdout = info(&["--encoding", "base64", "--hash-includes-name"]);
+    assert!(stdout.contains("Encoding:   base64"));
+    assert!(stdout.contains("Names:      each hash covers its path"));
+
+    let stdout = info(&["--short", "12"]);
+    assert!(stdout.contains("Short:      hashes cut to 12 characters"));
+}
+
+#[test]
+fn test_write_and_use_plan() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "keep.txt", b"Keep");
+    create_test_file(dir.path(), "skip.tmp", b"Skip");
+    let work = TempDir::new().unwrap();
+    let plan = work.path().join("plan.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-e", "*.tmp",
+            "--write-plan", plan.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&plan).unwrap(), "keep.txt\n");
+
+    // New files that appear later are ignored when running from the plan
+    create_test_file(dir.path(), "added.txt", b"Added");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--plan", plan.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("keep.txt"));
+    assert!(!stdout.contains("added.txt"));
+    assert!(!stdout.contains("skip.tmp"));
+}
+
+#[test]
+fn test_prefetch_matches_sequential_hash() {
+    let dir = TempDir::new().unwrap();
+    // Large enough to take the prefetch path, with a partial final chunk
+    let content: Vec<u8> = (0..10 * 1024 * 1024 + 123).map(|i| (i % 251) as u8).collect();
+    create_test_file(dir.path(), "large.bin", &content);
+
+    let mut outputs = Vec::new();
+    for prefetch in ["0", "4"] {
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-a", "blake2",
+                "--prefetch", prefetch
+            ])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        outputs.push(String::from_utf8(output.stdout).unwrap());
+    }
+
+    assert_eq!(outputs[0], outputs[1]);
+}
+
+#[test]
+fn test_size_budget() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "small.txt", &[b'a'; 10]);
+    create_test_file(dir.path(), "medium.txt", &[b'b'; 20]);
+    create_test_file(dir.path(), "large.txt", &[b'c'; 100]);
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--size-budget", "35"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("small.txt"));
+    assert!(stdout.contains("medium.txt"));
+    assert!(!stdout.contains("large.txt"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("included 2 files (30 bytes), skipped 1 files (100 bytes)"));
+}
+
+#[test]
+fn test_verify_file_replaced_by_directory() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "data", b"Was a file");
+    create_test_file(target_dir.path(), "data/inner.txt", b"Now a directory");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("FAILED: data - expected file but found directory"));
+}
+
+#[test]
+fn test_assume_unchanged() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "vendor/lib.txt", b"Vendored");
+    create_test_file(source_dir.path(), "app.txt", b"App");
+    create_test_file(target_dir.path(), "vendor/lib.txt", b"Modified but trusted");
+    create_test_file(target_dir.path(), "app.txt", b"App");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--assume-unchanged", "vendor/**",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let manifest = fs::read_to_string(&checksum_file).unwrap();
+    assert!(manifest.contains("\"hash\": \"assumed-unchanged\""));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Assumed:  1"));
+    assert!(stderr.contains("OK:       1"));
+}
+
+#[test]
+fn test_hash_includes_name() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "a.txt", b"Same content");
+    create_test_file(source_dir.path(), "b.txt", b"Same content");
+    create_test_file(target_dir.path(), "a.txt", b"Same content");
+    create_test_file(target_dir.path(), "b.txt", b"Same content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--hash-includes-name",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let manifest = fs::read_to_string(&checksum_file).unwrap();
+    assert!(manifest.contains("\"hash_includes_name\": true"));
+    let hashes: Vec<&str> = manifest
+        .lines()
+        .filter(|line| line.contains("\"hash\""))
+        .collect();
+    assert_eq!(hashes.len(), 2);
+    assert_ne!(hashes[0], hashes[1]);
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verify_through_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "valid.txt", b"Linked content");
+    create_test_file(source_dir.path(), "dangling.txt", b"Gone");
+    create_test_file(source_dir.path(), "retargeted.txt", b"Original target");
+
+    let target_dir = TempDir::new().unwrap();
+    let store = TempDir::new().unwrap();
+    let linked = create_test_file(store.path(), "linked.txt", b"Linked content");
+    let other = create_test_file(store.path(), "other.txt", b"Other target");
+    symlink(&linked, target_dir.path().join("valid.txt")).unwrap();
+    symlink(store.path().join("missing.txt"), target_dir.path().join("dangling.txt")).unwrap();
+    symlink(&other, target_dir.path().join("retargeted.txt")).unwrap();
+
+    let checksum_file = store.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "-v"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("OK: valid.txt"));
+    assert!(stderr.contains("FAILED: dangling.txt - dangling symlink"));
+    assert!(stderr.contains("FAILED: retargeted.txt - Hash mismatch"));
+}
+
+#[test]
+fn test_verify_dry_run() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "same.txt", b"Same");
+    create_test_file(source_dir.path(), "resized.txt", b"Short");
+    create_test_file(source_dir.path(), "missing.txt", b"Missing");
+    create_test_file(target_dir.path(), "same.txt", b"Diff");
+    create_test_file(target_dir.path(), "resized.txt", b"Much longer");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--skip-newer",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--dry-run"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // Same size but different content is not caught without hashing
+    assert!(stderr.contains("Present:       1"));
+    assert!(stderr.contains("Missing:       1"));
+    assert!(stderr.contains("Size mismatch: 1"));
+    assert!(!stderr.contains("Hash mismatch"));
+}
+
+#[test]
+fn test_manifest_dir_relative() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "meta/notes.txt", b"Notes");
+    create_test_file(dir.path(), "src/main.txt", b"Main");
+    let checksum_file = dir.path().join("meta/checksums.json");
+    fs::write(&checksum_file, b"stale manifest").unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--manifest-dir-relative",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let manifest = fs::read_to_string(&checksum_file).unwrap();
+    assert!(manifest.contains("\"path\": \"notes.txt\""));
+    assert!(manifest.contains("\"path\": \"../src/main.txt\""));
+    assert!(!manifest.contains("checksums.json"));
+
+    // The bundle verifies from the manifest's own directory
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().join("meta").to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_output_inside_tree_is_excluded() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "data.txt", b"Data");
+    let checksum_file = dir.path().join("checksums.json");
+
+    // Run twice so the second walk sees the manifest from the first run
+    for _ in 0..2 {
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+    }
+
+    let manifest = fs::read_to_string(&checksum_file).unwrap();
+    assert!(manifest.contains("data.txt"));
+    assert!(!manifest.contains("\"path\": \"checksums.json\""));
+}
+
+#[test]
+fn test_allow_missing() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "copied.txt", b"Copied");
+    create_test_file(source_dir.path(), "not-yet.txt", b"Not copied yet");
+    create_test_file(target_dir.path(), "copied.txt", b"Copied");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let verify = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                target_dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let output = verify(&[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Missing:  1"));
+    assert!(stderr.contains("Mismatch: 0"));
+
+    let output = verify(&["--allow-missing"]);
+    assert!(output.status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_verify_file_capabilities() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    let source = create_test_file(source_dir.path(), "tool", b"Binary");
+    let target = create_test_file(target_dir.path(), "tool", b"Binary");
+
+    // Setting capabilities needs setcap and CAP_SETFCAP; skip where unavailable
+    let setcap = |caps: &str, path: &Path| {
+        Command::new("setcap")
+            .args([caps, path.to_str().unwrap()])
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    };
+    if !setcap("cap_net_raw+ep", &source) || !setcap("cap_net_bind_service+ep", &target) {
+        return;
+    }
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--record-caps",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    assert!(content.contains("cap_net_raw"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--verify-caps"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "FAILED: tool - Capability mismatch: added cap_net_bind_service (permitted), removed cap_net_raw (permitted)"
+    ));
+}
+
+#[test]
+fn test_max_printed_failures() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_test_file(source_dir.path(), name, b"Content");
+    }
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--max-printed-failures", "2"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("FAILED:").count(), 2);
+    assert!(stderr.contains("... and 1 more failures"));
+    assert!(stderr.contains("Missing:  3"));
+}
+
+#[test]
+fn test_blake3_round_trip() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "test.txt", b"");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-a", "blake3",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // BLAKE3 digest of the empty input
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    assert!(content.contains("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_verify_rejects_digest_of_other_algorithm() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "test.txt", b"Test content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-a", "sha256",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // Relabel the SHA-256 manifest as SHA-512
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    fs::write(&checksum_file, content.replace("\"sha256\"", "\"sha512\"")).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("is not a sha512 digest"));
+}
+
+#[test]
+fn test_coreutils_format_round_trip() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "with space.txt", b"Spaced");
+    create_test_file(source_dir.path(), "sub/nested.txt", b"Nested");
+    if cfg!(unix) {
+        create_test_file(source_dir.path(), "back\\slash.txt", b"Escaped");
+    }
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("sums.sha256");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--format", "coreutils",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    assert!(content.contains(
+        "27aed53ce002295a1d6f8ab332b2660a2d4ef2a3a4d9eeffc4d36e27238f53d3  with space.txt\n"
+    ));
+    if cfg!(unix) {
+        assert!(content.lines().any(|line| line.starts_with('\\') && line.ends_with("  back\\\\slash.txt")));
+    }
+
+    // Standard tools accept the file as-is
+    if let Ok(status) = Command::new("sha256sum")
+        .args(["--quiet", "-c", checksum_file.to_str().unwrap()])
+        .current_dir(source_dir.path())
+        .status()
+    {
+        assert!(status.success());
+    }
+
+    // The .sha256 extension selects the coreutils reader and algorithm
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("using sha256 algorithm"));
+}
+
+#[test]
+fn test_bsd_format_round_trip() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "file (1).txt", b"BSD content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.txt");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "--format", "bsd",
+            "-a", "md5",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    assert_eq!(content, "MD5 (file (1).txt) = 731f2496e16018031cb9e7b0453a38b9\n");
+
+    // The algorithm comes from the tag, not from --algorithm
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--format", "bsd"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("using md5 algorithm"));
+}
+
+#[test]
+fn test_include_patterns() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "disk.iso", b"Include this");
+    create_test_file(dir.path(), "videos/clip.mp4", b"Include nested");
+    create_test_file(dir.path(), "videos/skip.mp4", b"Excluded anyway");
+    create_test_file(dir.path(), "notes.txt", b"Not included");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-i", "*.iso",
+            "-i", "**/*.mp4",
+            "-e", "*skip*"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("disk.iso"));
+    assert!(stdout.contains("clip.mp4"));
+    assert!(!stdout.contains("skip.mp4"));
+    assert!(!stdout.contains("notes.txt"));
+}
+
+#[test]
+fn test_gitignore() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), ".gitignore", b"*.log\nbuild/\n");
+    create_test_file(dir.path(), "src/main.rs", b"fn main() {}");
+    create_test_file(dir.path(), "src/.gitignore", b"generated.rs\n");
+    create_test_file(dir.path(), "src/generated.rs", b"// generated");
+    create_test_file(dir.path(), "build/out.bin", b"Build output");
+    create_test_file(dir.path(), "debug.log", b"Log");
+    create_test_file(dir.path(), ".git/HEAD", b"ref: refs/heads/main");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--gitignore"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("main.rs"));
+    assert!(stdout.contains(".gitignore"));
+    assert!(!stdout.contains("generated.rs"));
+    assert!(!stdout.contains("out.bin"));
+    assert!(!stdout.contains("debug.log"));
+    assert!(!stdout.contains("HEAD"));
+}
+
+#[test]
+fn test_compare_directories() {
+    let dir_a = TempDir::new().unwrap();
+    let dir_b = TempDir::new().unwrap();
+    create_test_file(dir_a.path(), "same.txt", b"Same");
+    create_test_file(dir_b.path(), "same.txt", b"Same");
+    create_test_file(dir_a.path(), "sub/changed.txt", b"Version A");
+    create_test_file(dir_b.path(), "sub/changed.txt", b"Version B");
+    create_test_file(dir_a.path(), "only_a.txt", b"A");
+    create_test_file(dir_b.path(), "only_b.txt", b"B");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir_a.path().to_str().unwrap(),
+            "--compare", dir_b.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to compare directories");
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stdout.contains(&format!("DIFFER: {}", Path::new("sub").join("changed.txt").display())));
+    assert!(stdout.contains("ONLY-IN-A: only_a.txt"));
+    assert!(stdout.contains("ONLY-IN-B: only_b.txt"));
+    assert!(!stdout.contains("same.txt"));
+    assert!(stderr.contains("OK:        1"));
+    assert!(stderr.contains("Total:     4"));
+}
+
+#[test]
+fn test_detect_added() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "original.txt", b"Original");
+
+    // Keep the checksum file inside the tree; it must not count as added
+    let checksum_file = dir.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap(),
+                "--detect-added"
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let output = verify();
+    assert!(output.status.success());
+
+    create_test_file(dir.path(), "new.txt", b"Added later");
+    let output = verify();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("FAILED: new.txt - Not in checksum file"));
+    assert!(stderr.contains("Added:    1"));
+    assert!(!stderr.contains("checksums.json - Not in checksum file"));
+}
+
+#[test]
+fn test_fail_fast() {
+    let source_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    for i in 0..20 {
+        let name = format!("file{:02}.txt", i);
+        create_test_file(source_dir.path(), &name, b"Original");
+        create_test_file(target_dir.path(), &name, b"Modified");
+    }
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // A single thread makes the cut-off point deterministic
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            target_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "-t", "1",
+            "--fail-fast"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Mismatch: 1\n"));
+    assert!(stderr.contains("Skipped:  19\n"));
+}
+
+#[test]
+fn test_library_generate_and_verify() {
+    use dirverify::{generate, verify, GenerateOptions, HashAlgorithm, VerifyOptions, VerifyResult};
+
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "a.txt", b"Library");
+    create_test_file(source_dir.path(), "sub/b.txt", b"Nested");
+
+    let manifest = generate(&GenerateOptions::new(source_dir.path(), HashAlgorithm::Blake3)).unwrap();
+    assert_eq!(manifest.algorithm, "blake3");
+    assert_eq!(manifest.entries.len(), 2);
+
+    let options = VerifyOptions::for_file(&manifest).unwrap();
+    let report = verify(&manifest, source_dir.path(), &options);
+    assert!(report.passed());
+
+    fs::write(source_dir.path().join("a.txt"), b"Changed").unwrap();
+    let report = verify(&manifest, source_dir.path(), &options);
+    assert!(!report.passed());
+    assert_eq!(report.count(|r| matches!(r, VerifyResult::Mismatch(_))), 1);
+}
+
+#[test]
+fn test_hash_stdin() {
+    use std::process::Stdio;
+
+    for (algo, expected) in [
+        ("sha256", "5fe45d159049a35afbf2530f91d76f00caa8fa3fda838c901d6f6d2373dc485b"),
+        ("md5", "a80aabb5a9d374bcd466d480eb129d90"),
+    ] {
+        let mut child = Command::new("cargo")
+            .args(["run", "--", "--stdin", "-a", algo])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to execute command");
+        child.stdin.take().unwrap().write_all(b"Piped content").unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", expected));
+    }
+}
+
+#[test]
+fn test_bytes_hashed_summary() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"0123456789");
+    create_test_file(dir.path(), "b.txt", b"abcde");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Hashed 15 bytes in "));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Bytes:    15 ("));
+}
+
+#[test]
+fn test_size_only() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "same_size.txt", b"Original");
+    create_test_file(source_dir.path(), "truncated.txt", b"Long original content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // Same-size edits go unnoticed without hashing
+    fs::write(source_dir.path().join("same_size.txt"), b"Modified").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--size-only"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    fs::write(source_dir.path().join("truncated.txt"), b"Long").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--size-only"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("truncated.txt - Size mismatch: expected 21 bytes, found 4"));
+    assert!(stderr.contains("Bytes:    0 ("));
+}
+
+#[test]
+fn test_size_recorded_without_modified() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap()])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"size\": 5"));
+    assert!(!stdout.contains("\"modified\""));
+}
+
+#[test]
+fn test_verify_only() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "good.txt", b"Good");
+    create_test_file(source_dir.path(), "sub/bad.txt", b"Original");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    fs::write(source_dir.path().join("sub/bad.txt"), b"Modified").unwrap();
+
+    let verify_only = |only: &str| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", source_dir.path().to_str().unwrap(),
+                "--only", only
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let output = verify_only("good.txt");
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Total:    1"));
+
+    let output = verify_only("sub/*");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("FAILED: sub/bad.txt"));
+
+    let output = verify_only("unknown.txt");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("No entry in the checksum file matches 'unknown.txt'"));
+}
+
+#[test]
+fn test_compressed_checksum_files() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Compressed");
+    create_test_file(dir.path(), "sub/b.txt", b"Round trip");
+
+    let manifests = TempDir::new().unwrap();
+    for (name, magic) in [
+        ("checksums.json.gz", &[0x1f, 0x8b][..]),
+        ("checksums.json.zst", &[0x28, 0xb5, 0x2f, 0xfd][..]),
+    ] {
+        let checksum_file = manifests.path().join(name);
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+        assert!(fs::read(&checksum_file).unwrap().starts_with(magic));
+
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert!(output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("OK:       2"));
+    }
+}
+
+#[test]
+fn test_jsonl_output() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Streamed");
+    create_test_file(dir.path(), "sub/b.txt", b"Line by line");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "--format", "jsonl"])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].contains("\"algorithm\":\"sha256\""));
+    assert!(lines[1..3].iter().all(|line| line.starts_with("{\"path\":")));
+    assert!(lines[3].starts_with("{\"count\":2,\"manifest_hash\":"));
+
+    // .jsonl names are read as JSONL, compressed or not
+    let manifests = TempDir::new().unwrap();
+    for name in ["checksums.jsonl", "checksums.jsonl.gz"] {
+        let checksum_file = manifests.path().join(name);
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--format", "jsonl",
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stderr).unwrap().contains("OK:       2"));
+    }
+}
+
+#[test]
+fn test_buffer_size() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "data.bin", &vec![7u8; 100_000]);
+
+    let hash_with = |buffer_size: &str| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--buffer-size", buffer_size
+            ])
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let default = hash_with("65536");
+    let small = hash_with("7");
+    assert!(default.status.success());
+    assert!(small.status.success());
+    assert_eq!(default.stdout, small.stdout);
+
+    let zero = hash_with("0");
+    assert!(!zero.status.success());
+    assert!(String::from_utf8(zero.stderr).unwrap().contains("greater than zero"));
+}
+
+#[test]
+fn test_mmap_matches_buffered_reads() {
+    use dirverify::hashing::{hash_file, hash_file_with_prefix};
+    use dirverify::{HashAlgorithm, HashOptions};
+
+    let dir = TempDir::new().unwrap();
+    // Above the 16 MiB mmap threshold, and not a multiple of the buffer size
+    let content: Vec<u8> = (0..17 * 1024 * 1024 + 123).map(|i| (i % 251) as u8).collect();
+    let path = create_test_file(dir.path(), "large.bin", &content);
+
+    let buffered = HashOptions::default();
+    let mapped = HashOptions {
+        mmap: true,
+        ..HashOptions::default()
+    };
+    for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3, HashAlgorithm::Xxh3] {
+        assert_eq!(
+            hash_file(&path, algorithm, &buffered).unwrap(),
+            hash_file(&path, algorithm, &mapped).unwrap()
+        );
+        assert_eq!(
+            hash_file_with_prefix(&path, b"large.bin", algorithm, &buffered).unwrap(),
+            hash_file_with_prefix(&path, b"large.bin", algorithm, &mapped).unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_max_depth() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "top.txt", b"Depth 1");
+    create_test_file(dir.path(), "a/middle.txt", b"Depth 2");
+    create_test_file(dir.path(), "a/b/deep.txt", b"Depth 3");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "--max-depth", "2"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("top.txt"));
+    assert!(stdout.contains("middle.txt"));
+    assert!(!stdout.contains("deep.txt"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "--max-depth", "0"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_record_and_follow_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "real.txt", b"Real content");
+    create_test_file(dir.path(), "other.txt", b"Other content");
+    symlink("real.txt", dir.path().join("link.txt")).unwrap();
+    symlink("nowhere.txt", dir.path().join("broken.txt")).unwrap();
+    // A cycle, which must not hang the scan when following links
+    symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    // Links are recorded with their targets, including broken ones
+    let json = fs::read_to_string(&checksum_file).unwrap();
+    let manifest: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let entry = |path: &str| {
+        manifest["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["path"] == path)
+            .cloned()
+            .unwrap()
+    };
+    assert_eq!(entry("link.txt")["kind"], "symlink");
+    assert_eq!(entry("link.txt")["link_target"], "real.txt");
+    assert_eq!(entry("link.txt")["hash"], "real.txt");
+    assert_eq!(entry("broken.txt")["link_target"], "nowhere.txt");
+    assert!(entry("real.txt").get("kind").is_none());
+    assert!(entry("real.txt").get("link_target").is_none());
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    assert!(verify().status.success());
+
+    fs::remove_file(dir.path().join("link.txt")).unwrap();
+    symlink("other.txt", dir.path().join("link.txt")).unwrap();
+    let output = verify();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "FAILED: link.txt - Symlink target changed: expected real.txt, found other.txt"
+    ));
+
+    // Following hashes the link's content and skips the broken link and the cycle
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "--follow-symlinks"])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("symlink"));
+    assert!(stdout.contains("\"link.txt\""));
+    assert!(!stdout.contains("broken.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verify_symlink_without_link_target() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "real.txt", b"Real content");
+    symlink("real.txt", dir.path().join("link.txt")).unwrap();
+
+    // Written before link_target existed, with the target only in hash
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    fs::write(
+        &checksum_file,
+        r#"{"version":"1.0","algorithm":"sha256","entries":[{"path":"link.txt","hash":"real.txt","kind":"symlink"}]}"#,
+    )
+    .unwrap();
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    assert!(verify().status.success());
+
+    fs::remove_file(dir.path().join("link.txt")).unwrap();
+    symlink("elsewhere.txt", dir.path().join("link.txt")).unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("expected real.txt, found elsewhere.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let script = create_test_file(dir.path(), "run.sh", b"#!/bin/sh\n");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--permissions",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    let json = fs::read_to_string(&checksum_file).unwrap();
+    assert!(json.contains(&format!("\"mode\": {}", 0o755)));
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    assert!(verify().status.success());
+
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o644)).unwrap();
+    let output = verify();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("FAILED: run.sh - Permission mismatch: expected 0755, found 0644"));
+}
+
+#[test]
+fn test_track_empty_dirs() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "full/file.txt", b"Not empty");
+    fs::create_dir_all(dir.path().join("logs/archive")).unwrap();
+    fs::create_dir(dir.path().join("cache")).unwrap();
+
+    // Opt-in only
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap()])
+        .output()
+        .expect("Failed to execute command");
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("directory"));
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--track-dirs",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    let json = fs::read_to_string(&checksum_file).unwrap();
+    assert_eq!(json.matches("\"kind\": \"directory\"").count(), 2);
+    assert!(json.contains("\"path\": \"cache\""));
+    assert!(!json.contains("\"path\": \"logs\""));
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    assert!(verify().status.success());
+
+    fs::remove_dir(dir.path().join("logs/archive")).unwrap();
+    create_test_file(dir.path(), "cache/new.txt", b"New");
+    let output = verify();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("FAILED: logs/archive - Directory not found"));
+    assert!(stderr.contains("FAILED: cache - expected empty directory but it has entries"));
+}
+
+#[test]
+fn test_verify_report() {
+    let source_dir = TempDir::new().unwrap();
+    create_test_file(source_dir.path(), "good.txt", b"Good");
+    create_test_file(source_dir.path(), "bad.txt", b"Original");
+    create_test_file(source_dir.path(), "gone.txt", b"Gone");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    fs::write(source_dir.path().join("bad.txt"), b"Modified").unwrap();
+    fs::remove_file(source_dir.path().join("gone.txt")).unwrap();
+    create_test_file(source_dir.path(), "new.txt", b"New");
+
+    let report_file = manifests.path().join("report.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            source_dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--detect-added",
+            "--report", report_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(!output.status.success());
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_file).unwrap()).unwrap();
+    assert_eq!(report["passed"], false);
+    assert_eq!(report["counts"]["ok"], 1);
+    assert_eq!(report["counts"]["mismatch"], 1);
+    assert_eq!(report["counts"]["missing"], 1);
+    assert_eq!(report["counts"]["added"], 1);
+    assert_eq!(report["counts"]["total"], 3);
+
+    let status = |path: &str| {
+        report["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["path"] == path)
+            .cloned()
+            .unwrap()
+    };
+    assert_eq!(status("good.txt")["status"], "ok");
+    assert!(status("good.txt").get("reason").is_none());
+    assert_eq!(status("bad.txt")["status"], "mismatch");
+    assert!(status("bad.txt")["reason"].as_str().unwrap().starts_with("Hash mismatch"));
+    assert_eq!(status("gone.txt")["status"], "missing");
+    assert_eq!(status("new.txt")["status"], "added");
+}
+
+#[test]
+fn test_detect_added_skips_own_outputs_in_tree() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Content");
+    let checksum_file = dir.path().join("sums.json");
+    let report_file = dir.path().join("rep.json");
+    let failures_file = dir.path().join("failed.txt");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // The failure list is open while the tree is scanned, and the second
+    // run finds the first run's report there too
+    for _ in 0..2 {
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap(),
+                "--detect-added",
+                "--report", report_file.to_str().unwrap(),
+                "--failures-out", failures_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Added:    0"));
+    }
+}
+
+#[test]
+fn test_strict_fails_on_skipped() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "stable.txt", b"Stable");
+    let touched = create_test_file(dir.path(), "touched.txt", b"Touched");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--skip-newer",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    fs::File::options()
+        .write(true)
+        .open(&touched)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+
+    let verify = |strict: bool| {
+        let mut args = vec![
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--skip-newer"
+        ];
+        if strict {
+            args.push("--strict");
+        }
+        Command::new("cargo")
+            .args(&args)
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let output = verify(false);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Skipped:  1"));
+
+    let output = verify(true);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Skipped:  1"));
+}
+
+#[test]
+fn test_exit_codes_distinguish_error_classes() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+    let manifests = TempDir::new().unwrap();
+
+    let verify = |checksum_file: &Path| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let missing = verify(&manifests.path().join("absent.json"));
+    assert_eq!(missing.status.code(), Some(2));
+    assert!(String::from_utf8(missing.stderr).unwrap().contains("Error:"));
+
+    let malformed = create_test_file(manifests.path(), "broken.json", b"{ not json");
+    assert_eq!(verify(&malformed).status.code(), Some(3));
+
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    fs::write(dir.path().join("test.txt"), b"Changed").unwrap();
+    assert_eq!(verify(&checksum_file).status.code(), Some(1));
+}
+
+#[test]
+fn test_update_reuses_unchanged_hashes() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "same.txt", b"Same");
+    create_test_file(dir.path(), "grown.txt", b"Short");
+
+    let manifests = TempDir::new().unwrap();
+    let previous = manifests.path().join("previous.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--skip-newer",
+            "-o", previous.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    // A reused hash is copied as-is, so a planted value proves the file wasn't read
+    let mut manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&previous).unwrap()).unwrap();
+    for entry in manifest["entries"].as_array_mut().unwrap() {
+        if entry["path"] == "same.txt" {
+            entry["hash"] = "planted".into();
+        }
+    }
+    fs::write(&previous, serde_json::to_string(&manifest).unwrap()).unwrap();
+
+    fs::write(dir.path().join("grown.txt"), b"Much longer").unwrap();
+    create_test_file(dir.path(), "new.txt", b"New");
+
+    let updated = manifests.path().join("updated.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--update", previous.to_str().unwrap(),
+            "-o", updated.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to update checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Reused 1 hashes, re-hashed 2 files"));
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&updated).unwrap()).unwrap();
+    let entries = manifest["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 3);
+    let same = entries.iter().find(|e| e["path"] == "same.txt").unwrap();
+    assert_eq!(same["hash"], "planted");
+    assert!(same["modified"].is_u64());
+}
+
+#[test]
+fn test_find_duplicates() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Shared");
+    create_test_file(dir.path(), "sub/b.txt", b"Shared");
+    create_test_file(dir.path(), "unique.txt", b"Unique");
+    create_test_file(dir.path(), "empty1.txt", b"");
+    create_test_file(dir.path(), "empty2.txt", b"");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "--find-duplicates"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("  a.txt\n  sub/b.txt\n"));
+    assert!(!stdout.contains("unique.txt"));
+    assert!(!stdout.contains("empty"));
+    assert!(!stdout.contains("\"entries\""));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "xxh3",
+            "--find-duplicates", "json"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let groups: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(groups.as_array().unwrap().len(), 1);
+    assert_eq!(groups[0]["paths"], serde_json::json!(["a.txt", "sub/b.txt"]));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: xxh3 collisions are possible"));
+    assert!(stderr.contains("Found 1 groups of duplicates (1 redundant files)"));
+}
+
+#[test]
+fn test_exclude_from_file() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "keep.txt", b"Keep");
+    create_test_file(dir.path(), "debug.log", b"Log");
+    create_test_file(dir.path(), "build/out/app.bin", b"Binary");
+    create_test_file(dir.path(), "scratch.tmp", b"Tmp");
+
+    let patterns = TempDir::new().unwrap();
+    let pattern_file = create_test_file(
+        patterns.path(),
+        "excludes.txt",
+        b"# build artifacts\n*.log\n\nbuild/**\n",
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--exclude-from", pattern_file.to_str().unwrap(),
+            "-e", "*.tmp"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("keep.txt"));
+    assert!(!stdout.contains("debug.log"));
+    assert!(!stdout.contains("app.bin"));
+    assert!(!stdout.contains("scratch.tmp"));
+}
+
+#[test]
+fn test_exclude_pattern_with_forward_slashes_matches_nested_path() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "src/lib.txt", b"Lib");
+    create_test_file(dir.path(), "vendor/pkg/.git/config", b"Config");
+    create_test_file(dir.path(), "vendor/pkg/cache/blob.bin", b"Blob");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-e", "**/.git/**",
+            "-e", "vendor/*/cache/*"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("lib.txt"));
+    assert!(!stdout.contains("config"));
+    assert!(!stdout.contains("blob.bin"));
+}
+
+#[test]
+fn test_invalid_pattern_warns_or_fails() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", dir.path().to_str().unwrap(), "-e", "[abc"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: invalid --exclude pattern '[abc'"));
+    assert!(String::from_utf8(output.stdout).unwrap().contains("test.txt"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-e", "[abc",
+            "--strict-patterns"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Error: invalid --exclude pattern '[abc'"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_generate_dry_run() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+    create_test_file(dir.path(), "sub/b.txt", b"123");
+    create_test_file(dir.path(), "skip.log", b"Log");
+    let out_dir = TempDir::new().unwrap();
+    let checksum_file = out_dir.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-e", "*.log",
+            "--dry-run",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "a.txt\nsub/b.txt\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Dry run: 2 files, 8 bytes would be hashed"));
+    assert!(!checksum_file.exists());
+}
+
+#[test]
+fn test_stored_paths_use_forward_slashes() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "sub/dir/file.txt", b"Nested");
+    create_test_file(dir.path(), "top.txt", b"Top");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checksum_file).unwrap()).unwrap();
+    let paths: Vec<&str> = manifest["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["sub/dir/file.txt", "top.txt"]);
+
+    // A copy of the tree verifies against the `/`-separated entries on any OS
+    let copy = TempDir::new().unwrap();
+    create_test_file(copy.path(), "sub/dir/file.txt", b"Nested");
+    create_test_file(copy.path(), "top.txt", b"Top");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            copy.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("OK:       2"));
+}
+
+#[cfg(not(feature = "network"))]
+#[test]
+fn test_checksum_url_needs_network_feature() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", "https://example.com/sums.json"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("built without the `network` feature"));
+}
+
+/// Serves `body` with `status` to each of `requests` connections on a local port.
+#[cfg(feature = "network")]
+fn serve_http(status: &'static str, body: Vec<u8>, requests: usize) -> String {
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let header = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    format!("http://{}/sums.json", address)
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn test_verify_against_checksum_url() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--features", "network", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let verify = |url: &str| {
+        Command::new("cargo")
+            .args([
+                "run", "--features", "network", "--",
+                dir.path().to_str().unwrap(),
+                "-c", url
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    let url = serve_http("200 OK", fs::read(&checksum_file).unwrap(), 1);
+    let output = verify(&url);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("OK:       1"));
+
+    let url = serve_http("404 Not Found", b"gone".to_vec(), 1);
+    let output = verify(&url);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("server returned 404"));
+
+    let url = serve_http("200 OK", b"<html>not json</html>".to_vec(), 1);
+    let output = verify(&url);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("is not a valid checksum file"));
+}
+
+#[test]
+fn test_read_threads_match_sequential_hashes() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..32 {
+        let content: Vec<u8> = (0..256 * 1024).map(|b| (b * (i + 1) % 251) as u8).collect();
+        create_test_file(dir.path(), &format!("data/file{:02}.bin", i), &content);
+    }
+
+    let run = |extra: &[&str]| {
+        let mut args = vec!["run", "--", dir.path().to_str().unwrap(), "--buffer-size", "16384"];
+        args.extend_from_slice(extra);
+        let started = std::time::Instant::now();
+        let output = Command::new("cargo")
+            .args(&args)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        (String::from_utf8(output.stdout).unwrap(), started.elapsed())
+    };
+
+    // Build first so neither timing includes compilation
+    run(&[]);
+    let (sequential, sequential_time) = run(&["--threads", "4"]);
+    let (split, split_time) = run(&["--hash-threads", "2", "--read-threads", "2"]);
+    eprintln!(
+        "4 threads: {:?}, 2 hash + 2 read threads: {:?}",
+        sequential_time, split_time
+    );
+
+    assert!(sequential.contains("data/file31.bin"));
+    assert_eq!(sequential, split);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_timeout_fails_stuck_file_and_continues() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "fine.txt", b"Fine");
+    // Opening a FIFO with no writer blocks, like a read from a stalled mount
+    let status = Command::new("mkfifo")
+        .arg(dir.path().join("stuck.pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // A plan bypasses the walk, and --allow-special keeps the FIFO in
+    let work = TempDir::new().unwrap();
+    let plan = create_test_file(work.path(), "plan.txt", b"fine.txt\nstuck.pipe\n");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--plan", plan.to_str().unwrap(),
+            "--file-timeout", "1",
+            "--allow-special"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("stuck.pipe: timed out after 1s"));
+    assert!(stderr.contains("Warning: 1 errors occurred during processing"));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("fine.txt"));
+    assert!(!stdout.contains("stuck.pipe"));
+}
+
+#[test]
+fn test_sri_format() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "hello.txt", b"hello");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("integrity.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--format", "sri",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    assert!(output.status.success());
+    let contents = fs::read_to_string(&checksum_file).unwrap();
+    assert_eq!(
+        contents,
+        "sha256-LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=  hello.txt\n"
+    );
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--format", "sri"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "md5",
+            "--format", "sri"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--format sri needs one of sha256, sha384, sha512, not md5"));
+}
+
+#[test]
+fn test_short_hashes_verify_by_prefix() {
+    let dir = TempDir::new().unwrap();
+    let file = create_test_file(dir.path(), "hello.txt", b"hello");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--short", "12",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--short 12 keeps 48 bits of each hash"));
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checksum_file).unwrap()).unwrap();
+    assert_eq!(manifest["short"], 12);
+    assert_eq!(manifest["entries"][0]["hash"], "2cf24dba5fb0");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    fs::write(&file, b"changed").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("nor a prefix of"));
+}
+
+#[test]
+fn test_verify_uses_per_entry_algorithm() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "new.txt", b"hello");
+    create_test_file(dir.path(), "old.txt", b"hello");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    fs::write(
+        &checksum_file,
+        r#"{
+  "version": "1.0",
+  "algorithm": "sha256",
+  "entries": [
+    {
+      "path": "new.txt",
+      "hash": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    },
+    {
+      "path": "old.txt",
+      "hash": "5d41402abc4b2a76b9719d911017c592",
+      "algorithm": "md5"
+    }
+  ]
+}"#,
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    fs::write(dir.path().join("old.txt"), b"changed").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("expected 5d41402abc4b2a76b9719d911017c592"));
+}
+
+#[test]
+fn test_quiet_only_reports_failures() {
+    let dir = TempDir::new().unwrap();
+    let file = create_test_file(dir.path(), "test.txt", b"Test content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--quiet",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("Scanning directory"));
+    assert!(!stderr.contains("Checksums written to"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--quiet", "--verbose"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("Verifying"));
+    assert!(!stderr.contains("OK: test.txt"));
+    assert!(!stderr.contains("Summary:"));
+
+    fs::write(&file, b"changed").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--quiet"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("FAILED: test.txt"));
+    assert!(stderr.contains("Summary:"));
+    assert!(!stderr.contains("Verifying"));
+}
+
+#[test]
+fn test_failures_out_lists_failed_paths() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "good.txt", b"good");
+    let changed = create_test_file(dir.path(), "changed.txt", b"before");
+    let removed = create_test_file(dir.path(), "removed.txt", b"gone soon");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    fs::write(&changed, b"after").unwrap();
+    fs::remove_file(&removed).unwrap();
+
+    let failures_file = manifests.path().join("failed.txt");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--failures-out", failures_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(1));
+
+    let mut failed: Vec<String> = fs::read_to_string(&failures_file)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    failed.sort();
+    assert_eq!(failed, ["changed.txt", "removed.txt"]);
+}
+
+#[test]
+fn test_absolute_paths_verify_without_directory() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test content");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--absolute-paths",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&content).unwrap();
+    let stored = json["entries"][0]["path"].as_str().unwrap();
+    let expected = fs::canonicalize(dir.path()).unwrap().join("test.txt");
+    assert_eq!(Path::new(stored), expected);
+
+    // DIRECTORY defaults to the current directory, which is not the tree
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", manifests.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("cannot be combined with --root"));
+}
+
+#[test]
+fn test_ignore_case_finds_renamed_files() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("Docs")).unwrap();
+    create_test_file(&dir.path().join("Docs"), "Readme.TXT", b"Read me");
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    fs::rename(dir.path().join("Docs"), dir.path().join("docs")).unwrap();
+    fs::rename(
+        dir.path().join("docs").join("Readme.TXT"),
+        dir.path().join("docs").join("readme.txt"),
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(!output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--ignore-case",
+            "--detect-added"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Warning: Docs/Readme.TXT was found as"));
+    assert!(stderr.contains("(names differ in case)"));
+}
+
+#[test]
+fn test_generate_output_is_sorted_and_stable() {
+    let dir = TempDir::new().unwrap();
+    for sub in ["b", "a", "c/d"] {
+        fs::create_dir_all(dir.path().join(sub)).unwrap();
+        for name in ["z.txt", "m.txt", "a.txt"] {
+            create_test_file(&dir.path().join(sub), name, sub.as_bytes());
+        }
+    }
+
+    let generate = || {
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--format", "coreutils"
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let first = generate();
+    let paths: Vec<&str> = first
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1)
+        .collect();
+    let mut sorted = paths.clone();
+    sorted.sort();
+    assert_eq!(paths.len(), 9);
+    assert_eq!(paths, sorted);
+    assert_eq!(generate(), first);
+}
+
+#[test]
+fn test_verify_missing_root_fails_once() {
+    let dir = TempDir::new().unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        create_test_file(dir.path(), name, name.as_bytes());
+    }
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let missing_root = manifests.path().join("not-there");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", missing_root.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let errors: Vec<&str> = stderr
+        .lines()
+        .filter(|line| line.starts_with("Error") || line.starts_with("FAILED"))
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("not-there does not exist"));
+}
+
+#[test]
+fn test_verify_manifest_detects_edited_entries() {
+    for (name, format) in [("checksums.json", "json"), ("checksums.jsonl", "jsonl")] {
+        let dir = TempDir::new().unwrap();
+        let file = create_test_file(dir.path(), "data.txt", b"before");
+
+        let manifests = TempDir::new().unwrap();
+        let checksum_file = manifests.path().join(name);
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--format", format,
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        let content = fs::read_to_string(&checksum_file).unwrap();
+        assert!(content.contains("\"manifest_hash\""));
+
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap(),
+                "--verify-manifest"
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert!(output.status.success(), "{}", name);
+
+        let output = Command::new("cargo")
+            .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+            .output()
+            .expect("Failed to run info");
+        assert!(String::from_utf8(output.stdout).unwrap().contains("Self-check: manifest_hash "));
+
+        // Change the file and its recorded hash together
+        fs::write(&file, b"after").unwrap();
+        fs::write(
+            &checksum_file,
+            content.replace(
+                "6db7d803e74f1ffa7d8f5adc0bf95b3e15bf4c8373fffadf546227cc6c6742cb",
+                "f39592393ef0859cb196a52693d2cea00fb2df784b3c04ae54aa7cadb8e562f8",
+            ),
+        )
+        .unwrap();
+
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert!(output.status.success(), "{}", name);
+
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-c", checksum_file.to_str().unwrap(),
+                "--verify-manifest"
+            ])
+            .output()
+            .expect("Failed to verify checksums");
+        assert_eq!(output.status.code(), Some(1), "{}", name);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("manifest_hash does not match"));
+    }
+}
+
+#[test]
+fn test_hmac_key_required_to_verify() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "hello.txt", b"hello");
+
+    let manifests = TempDir::new().unwrap();
+    let key_file = create_test_file(manifests.path(), "key", b"secret\n");
+    let key_arg = format!("@{}", key_file.display());
+    let checksum_file = manifests.path().join("checksums.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--hmac-key", &key_arg,
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+
+    // HMAC-SHA256("secret", "hello"); the key itself is never written
+    let content = fs::read_to_string(&checksum_file).unwrap();
+    assert!(content.contains("88aab3ede8d3adf94d26ab90d3bafd4a2083070c3bcce9c014ee04a443847c0b"));
+    assert!(content.contains("\"key_check\""));
+    assert!(!content.contains("secret"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--hmac-key", "secret"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap(),
+            "--hmac-key", "wrong"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--hmac-key does not match"));
+    assert!(!stderr.contains("FAILED"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("pass the key it was written with via --hmac-key"));
+}
+
+#[test]
+fn test_files_from_hashes_only_listed_files() {
+    use std::process::Stdio;
+
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "src/changed.rs", b"fn main() {}");
+    create_test_file(dir.path(), "src/untouched.rs", b"// same");
+    create_test_file(dir.path(), "README.md", b"# Readme");
+
+    let mut child = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--files-from", "-",
+            "--format", "coreutils"
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to execute command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"src/changed.rs\nREADME.md\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let paths: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1)
+        .collect();
+    assert_eq!(paths, ["README.md", "src/changed.rs"]);
+}
+
+#[test]
+fn test_changed_since_limits_verification() {
+    let dir = TempDir::new().unwrap();
+    let file = create_test_file(dir.path(), "old.txt", b"Original");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--skip-newer",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    fs::write(&file, b"Modified").unwrap();
+
+    // The recorded time is before 2100, so the corrupted file is skipped
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap(),
+            "--changed-since", "2100-01-01T00:00:00Z"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Skipped:  1"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap(),
+            "--changed-since", "946684800"
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_progress_json_events() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"A");
+    create_test_file(dir.path(), "b.txt", b"BB");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-o", checksum_file.to_str().unwrap(),
+            "--progress", "json"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap(),
+            "--progress", "json"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let (summary, progress) = events.split_last().unwrap();
+    assert!(progress.iter().all(|event| event["event"] == "progress"));
+    let last = progress.last().unwrap();
+    assert_eq!(last["phase"], "verify");
+    assert_eq!(last["processed"], 2);
+    assert_eq!(last["total"], 2);
+    assert_eq!(last["bytes"], 3);
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["passed"], true);
+    assert_eq!(summary["counts"]["ok"], 2);
+}
+
+#[test]
+fn test_output_identical_across_thread_counts() {
+    let dir = TempDir::new().unwrap();
+    for i in 0..40 {
+        let content = format!("file {}", i).repeat(i + 1);
+        create_test_file(dir.path(), &format!("d{}/f{}.txt", i % 5, i), content.as_bytes());
+    }
+    let manifests = TempDir::new().unwrap();
+
+    let outputs: Vec<Vec<u8>> = ["1", "4", "16"]
+        .iter()
+        .map(|threads| {
+            let checksum_file = manifests.path().join(format!("threads-{}.json", threads));
+            let output = Command::new("cargo")
+                .args([
+                    "run", "--",
+                    dir.path().to_str().unwrap(),
+                    "--threads", threads,
+                    "--permissions",
+                    "-o", checksum_file.to_str().unwrap()
+                ])
+                .output()
+                .expect("Failed to execute command");
+            assert!(output.status.success());
+            fs::read(&checksum_file).unwrap()
+        })
+        .collect();
+
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(outputs[0], outputs[2]);
+}
+
+#[test]
+fn test_base64_encoding_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let file = create_test_file(dir.path(), "test.txt", b"Test");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--encoding", "base64",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checksum_file).unwrap()).unwrap();
+    assert_eq!(manifest["encoding"], "base64");
+    // SHA-256 of "Test" in base64
+    assert_eq!(
+        manifest["entries"][0]["hash"],
+        "Uy6qvZV0iA2/drm4zACDLCCm7BE9aCKZVQ16bg80XiU="
+    );
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", dir.path().to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    assert!(verify().status.success());
+    fs::write(&file, b"Changed").unwrap();
+    assert_eq!(verify().status.code(), Some(1));
+}
+
+#[test]
+fn test_multiple_algorithms_in_one_pass() {
+    let dir = TempDir::new().unwrap();
+    let file = create_test_file(dir.path(), "test.txt", b"Test");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--algorithms", "sha256,md5",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checksum_file).unwrap()).unwrap();
+    assert_eq!(manifest["algorithm"], "sha256");
+    let entry = &manifest["entries"][0];
+    assert_eq!(
+        entry["hash"],
+        "532eaabd9574880dbf76b9b8cc00832c20a6ec113d682299550d7a6e0f345e25"
+    );
+    assert_eq!(entry["hashes"]["md5"], "0cbc6611f5540bd0809a388dc95a615b");
+
+    // A file edited along with its SHA-256 still fails on the MD5
+    fs::write(&file, b"Edit").unwrap();
+    let mut manifest = manifest;
+    manifest["entries"][0]["hash"] =
+        "464c4ffd019e1e9691dcf0537c797353ef2b1c1d4833d3d463e5b74ae4547344".into();
+    let edited = manifests.path().join("edited.json");
+    fs::write(&edited, manifest.to_string()).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", edited.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Hash mismatch (md5)"));
+}
+
+#[test]
+fn test_sidecar_files() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "release/app.iso", b"Test");
+    create_test_file(dir.path(), "release/notes.txt", b"Notes");
+    // A directory in the way of one sidecar makes it unwritable
+    fs::create_dir(dir.path().join("release/notes.txt.sha256")).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--sidecar"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Cannot write sidecar"));
+    assert!(stderr.contains("notes.txt.sha256"));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("release/app.iso.sha256")).unwrap(),
+        "532eaabd9574880dbf76b9b8cc00832c20a6ec113d682299550d7a6e0f345e25  app.iso\n"
+    );
+
+    // Sidecars from the earlier run are not hashed themselves, but a
+    // *.sha256 file without a sibling is ordinary content
+    fs::remove_dir(dir.path().join("release/notes.txt.sha256")).unwrap();
+    create_test_file(dir.path(), "release/lone.sha256", b"Not a sidecar");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--sidecar",
+            "-v"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(!dir.path().join("release/app.iso.sha256.sha256").exists());
+    assert!(dir.path().join("release/notes.txt.sha256").is_file());
+    assert!(dir.path().join("release/lone.sha256.sha256").is_file());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("app.iso.sha256: sidecar of"));
+    assert!(!stderr.contains("lone.sha256: sidecar of"));
+}
+
+#[test]
+fn test_verify_detects_checksum_lines_from_other_tools() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"Test");
+    create_test_file(dir.path(), "data/file.bin", b"Data");
+    let manifests = TempDir::new().unwrap();
+
+    // As written by `sha256sum test.txt` and `sha256sum -b data/file.bin`,
+    // under a name that doesn't reveal the format
+    let coreutils = manifests.path().join("release-checksums.txt");
+    fs::write(
+        &coreutils,
+        "532eaabd9574880dbf76b9b8cc00832c20a6ec113d682299550d7a6e0f345e25  test.txt\n\
+         cec3a9b89b2e391393d0f68e4bc12a9fa6cf358b3cdf79496dc442d52b8dd528 *data/file.bin\n",
+    )
+    .unwrap();
+    // As written by `shasum -a 256 --tag`
+    let bsd = manifests.path().join("release-tags.txt");
+    fs::write(
+        &bsd,
+        "SHA256 (test.txt) = 532eaabd9574880dbf76b9b8cc00832c20a6ec113d682299550d7a6e0f345e25\n\
+         SHA256 (data/file.bin) = cec3a9b89b2e391393d0f68e4bc12a9fa6cf358b3cdf79496dc442d52b8dd528\n",
+    )
+    .unwrap();
+    // md5sum output; coreutils lines don't name the algorithm, so -a does
+    let md5 = manifests.path().join("digests.json");
+    fs::write(
+        &md5,
+        "0cbc6611f5540bd0809a388dc95a615b  test.txt\n\
+         f6068daa29dbb05a7ead1e3b5a48bbee  data/file.bin\n",
+    )
+    .unwrap();
+
+    let verify = |checksum_file: &Path, extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", dir.path().to_str().unwrap()
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    for checksum_file in [&coreutils, &bsd] {
+        let output = verify(checksum_file, &[]);
+        assert!(output.status.success(), "{}", checksum_file.display());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("OK:       2"));
+    }
+    let output = verify(&md5, &["--input-format", "coreutils", "-a", "md5"]);
+    assert!(output.status.success());
+
+    fs::write(dir.path().join("test.txt"), b"Changed").unwrap();
+    assert_eq!(verify(&coreutils, &[]).status.code(), Some(1));
+}
+
+#[test]
+fn test_detect_coreutils_with_assumed_unchanged_lines() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a", b"Trusted");
+    create_test_file(dir.path(), "b", b"Hashed");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("sums.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--format", "coreutils",
+            "--assume-unchanged", "a",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let manifest = fs::read_to_string(&checksum_file).unwrap();
+    assert!(manifest.starts_with("assumed-unchanged  a\n"));
+
+    // The placeholder on the first line must not hide the format
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Assumed:  1"));
+    assert!(stderr.contains("OK:       1"));
+}
+
+#[test]
+fn test_xxh64_matches_canonical_digest() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "empty.bin", b"");
+    let manifests = TempDir::new().unwrap();
+    // As printed by `xxhsum -H1`: the canonical big-endian form, 16 hex chars
+    let checksum_file = manifests.path().join("sums.xxh64");
+    fs::write(&checksum_file, "ef46db3751d8e999  empty.bin\n").unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("using xxh64 algorithm"));
+}
+
+#[test]
+fn test_streaming_jsonl_verify() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"First");
+    create_test_file(dir.path(), "sub/b.txt", b"Second");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.jsonl");
+    Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--format", "jsonl",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+
+    let verify = || {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", dir.path().to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to verify checksums")
+    };
+
+    fs::write(dir.path().join("a.txt"), b"Changed").unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("as they are read"));
+    assert!(stderr.contains("Mismatch: 1"));
+    assert!(stderr.contains("Total:    2"));
+
+    // Entries before a malformed line are still checked
+    let text = fs::read_to_string(&checksum_file).unwrap();
+    let mut lines: Vec<&str> = text.lines().collect();
+    let trailer = lines.pop().unwrap();
+    fs::write(&checksum_file, format!("{}\nnot json\n", lines.join("\n"))).unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 4"));
+    assert!(stderr.contains("FAILED: a.txt"));
+
+    // So are those of a file cut short, which fails once they are
+    fs::write(&checksum_file, format!("{}\n", lines[..2].join("\n"))).unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("FAILED: a.txt"));
+    assert!(stderr.contains("ends without its trailer line; it may be truncated"));
+
+    // A trailer that doesn't match is caught when the file is loaded whole
+    fs::write(&checksum_file, format!("{}\n{}\n", lines[..2].join("\n"), trailer)).unwrap();
+    let output = Command::new("cargo")
+        .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+        .output()
+        .expect("Failed to run info");
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("declares 2 entries but contains 1"));
+}
+
+#[test]
+fn test_retries_transient_errors_only() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "fine.txt", b"Fine");
+    let status = Command::new("mkfifo")
+        .arg(dir.path().join("stuck.pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // A timeout may pass on a retry; a file that isn't there won't appear
+    let work = TempDir::new().unwrap();
+    let plan = create_test_file(work.path(), "plan.txt", b"fine.txt\nstuck.pipe\ngone.txt\n");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--plan", plan.to_str().unwrap(),
+            "--file-timeout", "1",
+            "--retries", "2",
+            "--allow-special"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("stuck.pipe: timed out after 1s"), "{}", stderr);
+    assert!(stderr.contains("1 files needed a retry to read"));
+    assert!(stderr.contains("Warning: 2 errors occurred during processing"));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("fine.txt"));
+}
+
+#[test]
+fn test_auto_algorithm_records_concrete_choice() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "data.bin", b"Accidental corruption only");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "auto",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checksum_file).unwrap()).unwrap();
+    assert_eq!(json["algorithm"], "xxh3");
+
+    // Verification goes by the recorded name, not -a
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "-c", checksum_file.to_str().unwrap(),
+            "-r", dir.path().to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("using xxh3 algorithm"));
+}
+
+#[test]
+fn test_null_terminated_coreutils() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "two\nlines.txt", b"Pathological");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--format", "coreutils",
+            "-0"
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    // Like sha256sum -z: no escaping, no newline after the record
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("  two\nlines.txt\0"), "{:?}", stdout);
+    assert_eq!(stdout.matches('\0').count(), 1);
+
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = create_test_file(manifests.path(), "SHA256SUMS", stdout.as_bytes());
+    let verify = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", dir.path().to_str().unwrap()
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    let output = verify(&["-0"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("OK:       1"));
+
+    fs::write(dir.path().join("two\nlines.txt"), b"Changed").unwrap();
+    assert_eq!(verify(&["--null"]).status.code(), Some(1));
+}
+
+#[test]
+fn test_diff_manifests() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "same.txt", b"Same");
+    create_test_file(dir.path(), "edited.txt", b"Before");
+    create_test_file(dir.path(), "deleted.txt", b"Gone later");
+    let manifests = TempDir::new().unwrap();
+    let generate = |name: &str| {
+        let checksum_file = manifests.path().join(name);
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        checksum_file
+    };
+    let before = generate("before.json");
+    fs::write(dir.path().join("edited.txt"), b"After").unwrap();
+    fs::remove_file(dir.path().join("deleted.txt")).unwrap();
+    create_test_file(dir.path(), "new.txt", b"New");
+    let after = generate("after.json");
+    // The tree is gone; only the manifests are read
+    drop(dir);
+
+    let report = manifests.path().join("diff.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--diff", before.to_str().unwrap(), after.to_str().unwrap(),
+            "--report", report.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to diff checksum files");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        ["ADDED: new.txt", "REMOVED: deleted.txt", "CHANGED: edited.txt"]
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unchanged: 1"));
+
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(json["identical"], false);
+    assert_eq!(json["counts"]["total"], 4);
+    assert_eq!(json["changed"][0], "edited.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--diff", after.to_str().unwrap(), after.to_str().unwrap()])
+        .output()
+        .expect("Failed to diff checksum files");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_special_files_are_skipped() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "fine.txt", b"Fine");
+    let status = Command::new("mkfifo")
+        .arg(dir.path().join("queue.pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // Neither the walk nor an explicit list opens the FIFO, which would block
+    let work = TempDir::new().unwrap();
+    let list = create_test_file(work.path(), "files.txt", b"fine.txt\nqueue.pipe\n");
+    for extra in [vec![], vec!["--files-from", list.to_str().unwrap()]] {
+        let output = Command::new("cargo")
+            .args(["run", "--", dir.path().to_str().unwrap()])
+            .args(&extra)
+            .output()
+            .expect("Failed to execute command");
+
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("fine.txt"));
+        assert!(!stdout.contains("queue.pipe"));
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(!stderr.contains("errors occurred"));
+        if !extra.is_empty() {
+            assert!(stderr.contains("queue.pipe: not a regular file"), "{}", stderr);
+        }
+    }
+}
+
+#[test]
+fn test_phase_timing_in_summary() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Timed");
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+
+    // --progress-percent needs the whole list, so the walk is its own phase
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--progress-percent",
+            "-o", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to generate checksums");
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let timing = stderr.lines().find(|line| line.starts_with("Time: ")).unwrap();
+    assert!(timing.contains("scan ") && timing.contains("hash ") && timing.contains("write "));
+    assert!(timing.contains("total "));
+
+    let verify = |extra: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run", "--",
+                "-c", checksum_file.to_str().unwrap(),
+                "-r", dir.path().to_str().unwrap()
+            ])
+            .args(extra)
+            .output()
+            .expect("Failed to verify checksums")
+    };
+    let stderr = String::from_utf8(verify(&[]).stderr).unwrap();
+    assert!(stderr.contains("  Time:     load "));
+    assert!(stderr.contains(", verify "));
+
+    let output = verify(&["--quiet"]);
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Time:"));
+}
+
+#[test]
+fn test_ignore_hidden() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "visible", b"Shown");
+    create_test_file(dir.path(), ".hidden", b"Dotfile");
+    create_test_file(dir.path(), ".cache/inside.txt", b"Under a hidden directory");
+    create_test_file(dir.path(), "sub/.env", b"Nested dotfile");
+    create_test_file(dir.path(), "sub/skip.tmp", b"Excluded");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--ignore-hidden",
+            "-e", "*.tmp"
+        ])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let paths: Vec<&str> = json["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths, ["visible"]);
+}
+
+#[test]
+fn test_threads_validation_and_report() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"Threads");
+
+    let run = |threads: &str| {
+        Command::new("cargo")
+            .args(["run", "--", dir.path().to_str().unwrap(), "--threads", threads])
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    let output = run("3");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Using 3 hashing threads\n"));
+    let output = run("1");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Using 1 hashing thread\n"));
+    let output = run("auto");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" (auto)\n"));
+
+    for (threads, reason) in [("-2", "cannot be negative"), ("100000", "more than the limit")] {
+        let output = run(threads);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains(reason));
+    }
+}
+
+#[test]
+fn test_text_normalize() {
+    let dir = TempDir::new().unwrap();
+    let manifest = dir.path().join("manifest.json");
+    let data = dir.path().join("data");
+    create_test_file(&data, "unix.txt", b"one\ntwo\n");
+    create_test_file(&data, "dos.txt", b"one\r\ntwo\r\n");
+    create_test_file(&data, "blob.bin", b"one\r\ntwo\r\n");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "--text-normalize", "*.txt",
+            "-o", manifest.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    let entry = |path: &str| {
+        json["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["path"] == path)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(entry("unix.txt")["hash"], entry("dos.txt")["hash"]);
+    assert_eq!(entry("dos.txt")["text"], true);
+    assert_ne!(entry("blob.bin")["hash"], entry("dos.txt")["hash"]);
+    assert!(entry("blob.bin").get("text").is_none());
+
+    // Converting line endings after generating still verifies
+    fs::write(data.join("unix.txt"), b"one\r\ntwo\r\n").unwrap();
+    fs::write(data.join("dos.txt"), b"one\ntwo\n").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "-c", manifest.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "--text-normalize", "*.txt",
+            "--format", "coreutils"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("JSON or JSONL"));
+}
+
+#[test]
+fn test_size_filters() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "tiny.txt", b"x");
+    create_test_file(dir.path(), "medium.txt", &[b'm'; 2048]);
+    create_test_file(dir.path(), "large.bin", &[b'l'; 8192]);
+
+    let run = |extra: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", dir.path().to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to execute command")
+    };
+    let paths = |output: &std::process::Output| -> Vec<String> {
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let mut paths: Vec<String> = json["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap().to_string())
+            .collect();
+        paths.sort();
+        paths
+    };
+
+    let output = run(&["--min-size", "1K", "--max-size", "4KiB", "--verbose"]);
+    assert!(output.status.success());
+    assert_eq!(paths(&output), ["medium.txt"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("tiny.txt: 1 bytes is below --min-size"));
+    assert!(stderr.contains("large.bin: 8192 bytes is above --max-size"));
+    assert!(stderr.contains("Skipped 2 files outside the size limits"));
+
+    // Lists bypass the walk but are filtered the same way
+    let list = dir.path().join("list.txt");
+    fs::write(&list, "tiny.txt\nlarge.bin\n").unwrap();
+    let output = run(&["--files-from", list.to_str().unwrap(), "--max-size", "1KB"]);
+    assert!(output.status.success());
+    assert_eq!(paths(&output), ["tiny.txt"]);
+
+    let output = run(&["--max-size", "10Q"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&["--min-size", "2M", "--max-size", "1M"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be larger than"));
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    let dir = TempDir::new().unwrap();
+    let data = dir.path().join("data");
+    let output_path = dir.path().join("out.json");
+    let checkpoint = data.join("checkpoint.jsonl");
+    let done = create_test_file(&data, "done.txt", b"Hashed before the crash");
+    create_test_file(&data, "todo.txt", b"Not reached yet");
+
+    // What an interrupted run leaves: a recorded entry and a torn last line
+    let modified = fs::metadata(&done)
+        .unwrap()
+        .modified()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let recorded = "a".repeat(64);
+    fs::write(
+        &checkpoint,
+        format!(
+            "{{\"version\":\"1.0\",\"algorithm\":\"sha256\"}}\n\
+             {{\"path\":\"done.txt\",\"hash\":\"{}\",\"modified\":{},\"size\":23}}\n\
+             {{\"path\":\"todo.t",
+            recorded, modified
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "--checkpoint", checkpoint.to_str().unwrap(),
+            "-o", output_path.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(1 entries)"));
+
+    // The recorded hash was kept instead of reading the file again
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    let entries = json["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["path"], "done.txt");
+    assert_eq!(entries[0]["hash"], recorded.as_str());
+    assert_eq!(entries[1]["path"], "todo.txt");
+    assert_ne!(entries[1]["hash"], recorded.as_str());
+    assert!(!checkpoint.exists());
+
+    // A checkpoint from a run with another algorithm is refused
+    fs::write(&checkpoint, "{\"version\":\"1.0\",\"algorithm\":\"md5\"}\n").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "--checkpoint", checkpoint.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("checkpoint uses md5"));
+}
+
+#[test]
+fn test_csv_format() {
+    let dir = TempDir::new().unwrap();
+    let data = dir.path().join("data");
+    let csv = dir.path().join("checksums.csv");
+    create_test_file(&data, "plain.txt", b"Plain");
+    create_test_file(&data, "a, \"quoted\" name.txt", b"Awkward");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "--format", "csv",
+            "-o", csv.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&csv).unwrap();
+    let mut lines = content.lines();
+    assert_eq!(lines.next(), Some("path,hash,size,modified"));
+    assert!(lines.next().unwrap().starts_with("\"a, \"\"quoted\"\" name.txt\","));
+    assert!(lines.next().unwrap().starts_with("plain.txt,"));
+    assert!(content.ends_with(",5,\n"));
+
+    let verify = || {
+        Command::new("cargo")
+            .args(["run", "--", data.to_str().unwrap(), "-c", csv.to_str().unwrap()])
+            .output()
+            .expect("Failed to execute command")
+    };
+    assert!(verify().status.success());
+
+    fs::write(data.join("a, \"quoted\" name.txt"), b"Changed").unwrap();
+    let output = verify();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("FAILED: a, \"quoted\" name.txt"));
+}
+
+#[test]
+fn test_self_test() {
+    let output = Command::new("cargo")
+        .args(["run", "--", "--self-test"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Self-test passed: 30 digests"));
+    assert!(!stderr.contains("hashing threads"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_ext_filter() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "disk.iso", b"Image");
+    create_test_file(dir.path(), "CAPS.IMG", b"Upper case");
+    create_test_file(dir.path(), "notes.txt", b"Text");
+    create_test_file(dir.path(), "archive.tar.gz", b"Tarball");
+    create_test_file(dir.path(), ".iso", b"Dotfile without an extension");
+    create_test_file(dir.path(), "old/backup.iso", b"Excluded");
+    create_test_file(dir.path(), "keep/copy.img", b"Included");
+
+    let paths = |extra: &[&str]| -> Vec<String> {
+        let output = Command::new("cargo")
+            .args(["run", "--", dir.path().to_str().unwrap()])
+            .args(extra)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let mut paths: Vec<String> = json["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["path"].as_str().unwrap().to_string())
+            .collect();
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(
+        paths(&["--ext", "iso", "--ext", ".img", "--ext", "tar.gz", "-e", "old/*"]),
+        ["CAPS.IMG", "archive.tar.gz", "disk.iso", "keep/copy.img"]
+    );
+    assert_eq!(paths(&["--ext", "IMG", "-i", "keep/*"]), ["keep/copy.img"]);
+}
+
+#[test]
+fn test_verify_subpath() {
+    let dir = TempDir::new().unwrap();
+    let data = dir.path().join("data");
+    create_test_file(&data, "projectX/a.txt", b"Restored");
+    create_test_file(&data, "projectX/sub/b.txt", b"Also restored");
+    create_test_file(&data, "projectXY/c.txt", b"Similar prefix, not restored");
+    create_test_file(&data, "other/d.txt", b"Not restored");
+
+    for manifest_name in ["manifest.json", "manifest.jsonl"] {
+        let manifest = dir.path().join(manifest_name);
+        let format = if manifest_name.ends_with(".jsonl") { "jsonl" } else { "json" };
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                data.to_str().unwrap(),
+                "--format", format,
+                "-o", manifest.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+    }
+    // Only projectX comes back from the backup, plus a new file outside it
+    fs::remove_dir_all(data.join("projectXY")).unwrap();
+    fs::remove_dir_all(data.join("other")).unwrap();
+    create_test_file(&data, "unrelated.txt", b"Not part of the restore");
+
+    for manifest_name in ["manifest.json", "manifest.jsonl"] {
+        let manifest = dir.path().join(manifest_name);
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                data.to_str().unwrap(),
+                "-c", manifest.to_str().unwrap(),
+                "--subpath", "./projectX/"
+            ])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("OK:       2"));
+        assert!(stderr.contains("Missing:  0"));
+        assert!(stderr.contains("Not selected: 2"));
+    }
+
+    let manifest = dir.path().join("manifest.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "-c", manifest.to_str().unwrap(),
+            "--subpath", "projectX",
+            "--detect-added"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "-c", manifest.to_str().unwrap(),
+            "--subpath", "projectZ"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No entry in the checksum file is under 'projectZ'"));
+}
+
+#[test]
+fn test_color_output() {
+    let dir = TempDir::new().unwrap();
+    let manifest = dir.path().join("manifest.json");
+    let data = dir.path().join("data");
+    create_test_file(&data, "good.txt", b"Unchanged");
+    create_test_file(&data, "bad.txt", b"Original");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", data.to_str().unwrap(), "-o", manifest.to_str().unwrap()])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    fs::write(data.join("bad.txt"), b"Modified").unwrap();
+
+    let verify = |color: Option<&str>| {
+        let mut command = Command::new("cargo");
+        command.args([
+            "run", "--",
+            data.to_str().unwrap(),
+            "-c", manifest.to_str().unwrap(),
+            "--verbose"
+        ]);
+        if let Some(color) = color {
+            command.args(["--color", color]);
+        }
+        let output = command.output().expect("Failed to execute command");
+        assert_eq!(output.status.code(), Some(1));
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    };
+
+    let stderr = verify(Some("always"));
+    assert!(stderr.contains("\x1b[31mFAILED:\x1b[0m bad.txt"));
+    assert!(stderr.contains("\x1b[32mOK:\x1b[0m good.txt"));
+
+    // Captured output isn't a terminal, so auto leaves it plain
+    for color in [None, Some("never")] {
+        let stderr = verify(color);
+        assert!(stderr.contains("FAILED: bad.txt"));
+        assert!(!stderr.contains('\x1b'));
+    }
+}
+
+#[test]
+fn test_jsonl_output_inside_hashed_tree() {
+    let dir = TempDir::new().unwrap();
+    let manifest = dir.path().join("sums.jsonl");
+    create_test_file(dir.path(), "a.txt", b"Alpha");
+    create_test_file(dir.path(), "sub/b.txt", b"Beta");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "--format", "jsonl",
+            "-o", manifest.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let content = fs::read_to_string(&manifest).unwrap();
+    assert_eq!(content.lines().count(), 4);
+    assert!(!content.contains("sums.jsonl"));
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", manifest.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_truncated_hash_without_short_header_fails() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "hello.txt", b"hello");
+
+    // A hash cut short by hand, in a manifest not written with --short
+    let manifests = TempDir::new().unwrap();
+    let checksum_file = manifests.path().join("checksums.json");
+    fs::write(
+        &checksum_file,
+        r#"{"version":"1.0","algorithm":"sha256","entries":[{"path":"hello.txt","hash":"2c"}]}"#,
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-c", checksum_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to verify checksums");
+    assert_eq!(output.status.code(), Some(1));
+
+    // Nor does --short work where its length can't be recorded, or on HMACs
+    for flags in [["--format", "coreutils"], ["--hmac-key", "secret"]] {
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--short", "12",
+            ])
+            .args(flags)
+            .output()
+            .expect("Failed to run dirverify");
+        assert_eq!(output.status.code(), Some(2));
+    }
+}
+
+#[test]
+fn test_compare_report() {
+    let dir_a = TempDir::new().unwrap();
+    let dir_b = TempDir::new().unwrap();
+    create_test_file(dir_a.path(), "same.txt", b"Same");
+    create_test_file(dir_b.path(), "same.txt", b"Same");
+    create_test_file(dir_a.path(), "changed.txt", b"hello");
+    create_test_file(dir_b.path(), "changed.txt", b"Version B");
+    create_test_file(dir_a.path(), "only_a.txt", b"A");
+    create_test_file(dir_b.path(), "only_b.txt", b"B");
+
+    let reports = TempDir::new().unwrap();
+    let report_file = reports.path().join("compare.json");
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir_a.path().to_str().unwrap(),
+            "--compare", dir_b.path().to_str().unwrap(),
+            "--compare-report", report_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to compare directories");
+
+    assert_eq!(output.status.code(), Some(1));
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_file).unwrap()).unwrap();
+    assert_eq!(report["identical"], false);
+    assert_eq!(report["counts"]["ok"], 1);
+    assert_eq!(report["counts"]["differ"], 1);
+    assert_eq!(report["counts"]["total"], 4);
+    assert_eq!(report["differ"][0]["path"], "changed.txt");
+    assert_eq!(
+        report["differ"][0]["hash_a"],
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    );
+    assert_ne!(report["differ"][0]["hash_b"], report["differ"][0]["hash_a"]);
+    assert_eq!(report["only_in_a"], serde_json::json!(["only_a.txt"]));
+    assert_eq!(report["only_in_b"], serde_json::json!(["only_b.txt"]));
+
+    // Identical trees still exit 0 with a report
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir_a.path().to_str().unwrap(),
+            "--compare", dir_a.path().to_str().unwrap(),
+            "--compare-report", report_file.to_str().unwrap()
+        ])
+        .output()
+        .expect("Failed to compare directories");
+    assert!(output.status.success());
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report_file).unwrap()).unwrap();
+    assert_eq!(report["identical"], true);
+}
+
+#[test]
+fn test_comma_separated_algorithm_flag() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "test.txt", b"hello");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "sha256,blake3"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(manifest["algorithm"], "sha256");
+    let entry = &manifest["entries"][0];
+    assert_eq!(
+        entry["hash"],
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+    );
+    assert_eq!(
+        entry["hashes"]["blake3"],
+        "ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f"
+    );
+
+    // Every value is checked, and the error lists the valid ones
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            dir.path().to_str().unwrap(),
+            "-a", "sha256,sha3"
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid value 'sha3'"));
+    assert!(stderr.contains("possible values"));
+}
+
+#[test]
+fn test_verify_checksum_file_from_stdin() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "hello.txt", b"hello");
+    let manifests = TempDir::new().unwrap();
+
+    for format in ["json", "jsonl", "coreutils"] {
+        let checksum_file = manifests.path().join(format!("checksums.{}", format));
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--format", format,
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+
+        let verify = || {
+            Command::new("cargo")
+                .args([
+                    "run", "--",
+                    dir.path().to_str().unwrap(),
+                    "-c", "-"
+                ])
+                .stdin(fs::File::open(&checksum_file).unwrap())
+                .output()
+                .expect("Failed to verify checksums")
+        };
+        let output = verify();
+        assert!(output.status.success(), "{} from stdin should verify", format);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("OK:       1"), "{}", stderr);
+
+        fs::write(dir.path().join("hello.txt"), b"changed").unwrap();
+        assert_eq!(verify().status.code(), Some(1), "{} from stdin", format);
+        fs::write(dir.path().join("hello.txt"), b"hello").unwrap();
+    }
+}
+
+#[test]
+fn test_manifest_info_on_other_formats() {
+    let dir = TempDir::new().unwrap();
+    create_test_file(dir.path(), "a.txt", b"12345");
+    create_test_file(dir.path(), "b.txt", b"123");
+    let manifests = TempDir::new().unwrap();
+
+    for (name, format, algorithm) in [
+        ("SHA256SUMS", "coreutils", "sha256"),
+        ("checksums.jsonl", "jsonl", "md5"),
+    ] {
+        let checksum_file = manifests.path().join(name);
+        let output = Command::new("cargo")
+            .args([
+                "run", "--",
+                dir.path().to_str().unwrap(),
+                "--format", format,
+                "-a", algorithm,
+                "-o", checksum_file.to_str().unwrap()
+            ])
+            .output()
+            .expect("Failed to generate checksums");
+        assert!(output.status.success());
+
+        let output = Command::new("cargo")
+            .args(["run", "--", "--info", checksum_file.to_str().unwrap()])
+            .output()
+            .expect("Failed to run info");
+
+        assert!(output.status.success(), "--info on {}", name);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains(&format!("Format:     {}", format)));
+        assert!(stdout.contains(&format!("Algorithm:  {}", algorithm)));
+        assert!(stdout.contains("Entries:    2"));
+    }
+}